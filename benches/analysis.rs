@@ -0,0 +1,112 @@
+// Timings for the analysis pipeline on generated markets, so we notice when
+// something makes a full scan too slow for watch mode. `cargo bench` runs
+// them, `cargo bench -- build_bridges` just the one group.
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+
+use poe_flip_finder::logic;
+use poe_flip_finder::models::api_models::ExchangeRecord;
+use poe_flip_finder::models::logic_models::{AnalysisParams, TradingCurrencyRates};
+use poe_flip_finder::synth::{self, SynthConfig};
+
+// Roughly how many currencies poe2scout lists against the hubs, and ten times
+// that for when we scan the whole exchange
+const SIZES: [(&str, usize); 2] = [("real", 400), ("10x", 4000)];
+
+struct Market {
+    // What analyze hands the bridge search: big enough pairs with a hub on
+    // one side
+    bridges: Vec<ExchangeRecord>,
+    base_rates: TradingCurrencyRates,
+    all_records: Vec<ExchangeRecord>,
+}
+
+fn market(currencies: usize) -> Market {
+    let config = SynthConfig {
+        currencies,
+        planted_loops: currencies / 20,
+        ..Default::default()
+    };
+    let all_records = synth::generate(1, 1, &config).records;
+    let params = AnalysisParams::default();
+    let mut base_rates = TradingCurrencyRates::default();
+    logic::get_base_prices(&all_records, &mut base_rates);
+    let bridges = all_records
+        .iter()
+        .filter(|record| record.volume >= params.min_vol && record.is_valid_bridge())
+        .cloned()
+        .collect();
+    Market {
+        bridges,
+        base_rates,
+        all_records,
+    }
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let params = AnalysisParams::default();
+    let markets: Vec<(&str, Market)> = SIZES
+        .iter()
+        .map(|&(label, currencies)| (label, market(currencies)))
+        .collect();
+
+    let mut group = c.benchmark_group("build_hub_bridge_maps");
+    for (label, market) in &markets {
+        group.throughput(Throughput::Elements(market.bridges.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(label), market, |b, market| {
+            b.iter(|| logic::build_hub_bridge_maps(black_box(&market.bridges)))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("build_bridges");
+    for (label, market) in &markets {
+        let maps = logic::build_hub_bridge_maps(&market.bridges);
+        group.throughput(Throughput::Elements(maps.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.iter(|| logic::build_bridges(black_box(&maps)))
+        });
+    }
+    group.finish();
+
+    // The eval_profit pass and sort analyze does on build_bridges' output
+    let mut group = c.benchmark_group("filter_routes");
+    for (label, market) in &markets {
+        let routes = logic::build_bridges(&logic::build_hub_bridge_maps(&market.bridges));
+        group.throughput(Throughput::Elements(routes.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.iter_batched(
+                || routes.clone(),
+                |mut routes| {
+                    routes.retain(|route| {
+                        logic::eval_profit(route, &market.base_rates, params.min_profit_frac)
+                    });
+                    routes.sort_by(|a, b| b.implied_rate.total_cmp(&a.implied_rate));
+                    routes
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    // Everything together, validation and all, for comparing against the
+    // pieces
+    let mut group = c.benchmark_group("analyze");
+    for (label, market) in &markets {
+        group.throughput(Throughput::Elements(market.all_records.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.iter_batched(
+                || market.all_records.clone(),
+                |records| logic::analyze(records, Some(1), &params),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::AlertConfig;
+use crate::models::logic_models::{Opportunity, RouteKey};
+
+// Discord won't take a message over 2000 characters, this keeps us well
+// under it
+const DISCORD_MAX_ROUTES: usize = 15;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertFormat {
+    // {"content": "..."}, which is also what Slack-ish webhooks want
+    #[default]
+    Discord,
+    // Our own opportunity objects
+    Generic,
+}
+
+#[derive(Serialize)]
+struct GenericPayload<'a> {
+    snapshot_epoch: u64,
+    opportunities: &'a [&'a Opportunity],
+}
+
+pub struct Alerter {
+    config: AlertConfig,
+    url: String,
+    client: Client,
+    // Route -> when we last told someone about it
+    last_sent: HashMap<RouteKey, u64>,
+}
+
+impl Alerter {
+    // None when there's no webhook configured
+    pub fn new(config: &AlertConfig, client: Client) -> Option<Alerter> {
+        let url = config.webhook_url.clone()?;
+        Some(Alerter {
+            config: config.clone(),
+            url,
+            client,
+            last_sent: HashMap::new(),
+        })
+    }
+
+    // Sends one message with every route past the threshold that isn't
+    // cooling down. A route only counts as sent if the webhook took it, so a
+    // failed send gets another go next snapshot. Returns how many went out.
+    pub fn send(
+        &mut self,
+        epoch: u64,
+        opportunities: &[Opportunity],
+        now: u64,
+    ) -> Result<usize, reqwest::Error> {
+        let due: Vec<&Opportunity> = opportunities
+            .iter()
+            .filter(|o| o.margin.abs() >= self.config.min_margin)
+            .filter(|o| match self.last_sent.get(&o.route()) {
+                Some(&sent_at) => now.saturating_sub(sent_at) >= self.config.cooldown_secs,
+                None => true,
+            })
+            .collect();
+        if due.is_empty() {
+            return Ok(0);
+        }
+
+        let body = match self.config.format {
+            AlertFormat::Discord => discord_payload(epoch, &due),
+            AlertFormat::Generic => serde_json::to_value(GenericPayload {
+                snapshot_epoch: epoch,
+                opportunities: &due,
+            })
+            .expect("Opportunities always serialize"),
+        };
+        self.post(&body)?;
+
+        for opportunity in &due {
+            self.last_sent.insert(opportunity.route(), now);
+        }
+        Ok(due.len())
+    }
+
+    fn post(&self, body: &serde_json::Value) -> Result<(), reqwest::Error> {
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .client
+                .post(&self.url)
+                .json(body)
+                .send()
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.config.retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "Webhook failed, retry {} of {}: {}",
+                        attempt, self.config.retries, e
+                    );
+                    thread::sleep(Duration::from_secs(self.config.retry_secs));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn discord_payload(epoch: u64, opportunities: &[&Opportunity]) -> serde_json::Value {
+    let mut lines = vec![format!(
+        "**Snapshot {}**: {} routes past the alert threshold",
+        epoch,
+        opportunities.len()
+    )];
+    for o in opportunities.iter().take(DISCORD_MAX_ROUTES) {
+        lines.push(format!(
+            "{} -> {} -> {}: {:+.2}% ({:.3} vs {:.3})",
+            o.hub_one,
+            o.bridge,
+            o.hub_two,
+            o.margin * 100.0,
+            o.implied_rate,
+            o.reference_rate
+        ));
+    }
+    if opportunities.len() > DISCORD_MAX_ROUTES {
+        lines.push(format!(
+            "...and {} more",
+            opportunities.len() - DISCORD_MAX_ROUTES
+        ));
+    }
+    json!({ "content": lines.join("\n") })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // Answers each request with the next status in `statuses` and sends the
+    // bodies it got back down the channel
+    fn listener(statuses: Vec<u16>) -> (String, mpsc::Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(length) = line.strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    fn alerter(url: String, format: AlertFormat) -> Alerter {
+        let config = AlertConfig {
+            webhook_url: Some(url),
+            format,
+            min_margin: 0.1,
+            cooldown_secs: 600,
+            retries: 2,
+            retry_secs: 0,
+        };
+        Alerter::new(&config, Client::new()).unwrap()
+    }
+
+    #[test]
+    fn test_no_url_no_alerter() {
+        assert!(Alerter::new(&AlertConfig::default(), Client::new()).is_none());
+    }
+
+    #[test]
+    fn test_threshold_and_cooldown() {
+        let (url, bodies) = listener(vec![204, 204]);
+        let mut alerter = alerter(url, AlertFormat::Generic);
+        let opportunities = vec![
+            Opportunity::sample("Vaal Orb", 0.15),
+            Opportunity::sample("Regal Orb", 0.05),
+            Opportunity::sample("Orb of Annulment", -0.2),
+        ];
+
+        assert_eq!(alerter.send(7, &opportunities, 1000).unwrap(), 2);
+        let body = bodies.recv().unwrap();
+        assert_eq!(body["snapshot_epoch"], 7);
+        assert_eq!(body["opportunities"][0]["bridge"], "Vaal Orb");
+        assert_eq!(body["opportunities"][1]["bridge"], "Orb of Annulment");
+
+        // Still cooling down, nothing gets posted
+        assert_eq!(alerter.send(8, &opportunities, 1300).unwrap(), 0);
+
+        // Cooled down
+        assert_eq!(alerter.send(9, &opportunities[..1], 1600).unwrap(), 1);
+        let body = bodies.recv().unwrap();
+        assert_eq!(body["snapshot_epoch"], 9);
+    }
+
+    #[test]
+    fn test_retries_then_succeeds() {
+        let (url, bodies) = listener(vec![500, 503, 200]);
+        let mut alerter = alerter(url, AlertFormat::Discord);
+        let opportunities = vec![Opportunity::sample("Vaal Orb", 0.15)];
+
+        assert_eq!(alerter.send(7, &opportunities, 1000).unwrap(), 1);
+        let bodies: Vec<serde_json::Value> = bodies.iter().collect();
+        assert_eq!(bodies.len(), 3);
+        let content = bodies[2]["content"].as_str().unwrap();
+        assert!(content.contains("Divine -> Vaal Orb -> Exalt: +15.00%"));
+    }
+
+    #[test]
+    fn test_gives_up_and_tries_again_next_time() {
+        let (url, _bodies) = listener(vec![500, 500, 500, 200]);
+        let mut alerter = alerter(url, AlertFormat::Discord);
+        let opportunities = vec![Opportunity::sample("Vaal Orb", 0.15)];
+
+        assert!(alerter.send(7, &opportunities, 1000).is_err());
+        // It didn't go out so it isn't cooling down
+        assert_eq!(alerter.send(8, &opportunities, 1001).unwrap(), 1);
+    }
+}
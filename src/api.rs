@@ -1,15 +1,60 @@
+//! Thin wrappers around the poe2scout endpoints we use. Everything is
+//! blocking and for the one league in [`LEAGUE`].
+
 use reqwest::{Result, blocking::Client};
 
 use crate::models::api_models::{ExchangeRecord, ExchangeSnapshot};
 
-pub fn get_exchange_snapshot(client: &Client) -> Result<ExchangeSnapshot> {
-    let url = "https://poe2scout.com/api/currencyExchangeSnapshot?league=Rise%20of%20the%20Abyssal";
+/// The league every request asks about
+pub const LEAGUE: &str = "Rise of the Abyssal";
+
+/// A client with the user agent poe2scout asks callers to set, reuse it
+/// for every call
+pub fn build_client() -> Result<Client> {
+    Client::builder()
+        .user_agent("poe-flip-finder/1.0-camiam144@gmail.com")
+        .build()
+}
+
+fn league_param() -> String {
+    LEAGUE.replace(' ', "%20")
+}
+
+fn snapshot_url() -> String {
+    format!(
+        "https://poe2scout.com/api/currencyExchangeSnapshot?league={}",
+        league_param()
+    )
+}
 
-    client.get(url).send()?.json()
+fn pairs_url() -> String {
+    format!(
+        "https://poe2scout.com/api/currencyExchange/SnapshotPairs?league={}",
+        league_param()
+    )
 }
 
+/// The newest snapshot's metadata, mostly for its epoch
+pub fn get_exchange_snapshot(client: &Client) -> Result<ExchangeSnapshot> {
+    client.get(snapshot_url()).send()?.json()
+}
+
+/// Every currency pair in the newest snapshot
 pub fn get_newest_snapshot_pairs(client: &Client) -> Result<Vec<ExchangeRecord>> {
-    let url =
-        "https://poe2scout.com/api/currencyExchange/SnapshotPairs?league=Rise%20of%20the%20Abyssal";
-    client.get(url).send()?.json()
+    client.get(pairs_url()).send()?.json()
+}
+
+/// [`get_exchange_snapshot`]'s response body as poe2scout sent it, for
+/// recording fixtures
+pub fn get_exchange_snapshot_raw(client: &Client) -> Result<String> {
+    client
+        .get(snapshot_url())
+        .send()?
+        .error_for_status()?
+        .text()
+}
+
+/// [`get_newest_snapshot_pairs`]'s response body as poe2scout sent it
+pub fn get_newest_snapshot_pairs_raw(client: &Client) -> Result<String> {
+    client.get(pairs_url()).send()?.error_for_status()?.text()
 }
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::logic;
+use crate::models::api_models::ExchangeRecord;
+use crate::models::logic_models::{AnalysisParams, Opportunity, RouteKey, TradingCurrencyType};
+use crate::source::{self, MarketSource, SourceResult};
+
+// Lets every route through, so we can price whatever we signalled on in the
+// next snapshot even if it wouldn't make the cut there anymore
+const EVERY_ROUTE: AnalysisParams = AnalysisParams {
+    min_vol: 0.0,
+    min_profit_frac: 0.0,
+};
+
+// One signal from one snapshot, filled at the prices in the next one
+#[derive(Debug, Clone, Serialize)]
+pub struct Trade {
+    pub signal_epoch: u64,
+    pub executed_epoch: u64,
+    pub hub_one: TradingCurrencyType,
+    pub bridge: String,
+    pub hub_two: TradingCurrencyType,
+    // What the signal snapshot promised and what we actually got, both going
+    // whichever way round was profitable at the signal
+    pub expected_return: f64,
+    pub realized_return: f64,
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestSummary {
+    pub params: AnalysisParams,
+    pub stake: f64,
+    pub snapshots: usize,
+    pub first_epoch: Option<u64>,
+    pub last_epoch: Option<u64>,
+    pub trade_count: usize,
+    // Signals whose route was gone by the next snapshot
+    pub unfilled: usize,
+    pub total_pnl: f64,
+    pub hit_rate: f64,
+    // Biggest drop in cumulative P&L from a previous high
+    pub max_drawdown: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestReport {
+    #[serde(flatten)]
+    pub summary: BacktestSummary,
+    pub trades: Vec<Trade>,
+}
+
+// A positive margin means going hub_one -> bridge -> hub_two beats the direct
+// trade, a negative one means going the other way round does
+fn route_return(margin: f64, forward: bool) -> f64 {
+    if forward {
+        margin
+    } else {
+        1.0 / (1.0 + margin) - 1.0
+    }
+}
+
+// Replays the snapshots in the order given. Every opportunity found in one
+// snapshot gets `stake` exalts put through it at the next snapshot's prices,
+// which is about as fast as we could actually act on it.
+pub fn backtest(
+    snapshots: impl IntoIterator<Item = (u64, Vec<ExchangeRecord>)>,
+    params: &AnalysisParams,
+    stake: f64,
+) -> BacktestReport {
+    let mut trades = Vec::new();
+    let mut unfilled = 0;
+    let mut snapshot_count = 0;
+    let mut first_epoch = None;
+    let mut last_epoch = None;
+    let (mut cumulative, mut peak, mut max_drawdown) = (0.0f64, 0.0f64, 0.0f64);
+    let mut signals: Vec<Opportunity> = Vec::new();
+    let mut signal_epoch = 0;
+
+    for (epoch, records) in snapshots {
+        snapshot_count += 1;
+        first_epoch.get_or_insert(epoch);
+        last_epoch = Some(epoch);
+
+        let margins: HashMap<RouteKey, f64> =
+            logic::analyze(records.clone(), Some(epoch), &EVERY_ROUTE)
+                .opportunities
+                .iter()
+                .map(|o| (o.route(), o.margin))
+                .collect();
+
+        for signal in &signals {
+            let Some(&margin) = margins.get(&signal.route()) else {
+                unfilled += 1;
+                continue;
+            };
+            let forward = signal.margin >= 0.0;
+            let realized_return = route_return(margin, forward);
+            let pnl = stake * realized_return;
+            cumulative += pnl;
+            trades.push(Trade {
+                signal_epoch,
+                executed_epoch: epoch,
+                hub_one: signal.hub_one,
+                bridge: signal.bridge.clone(),
+                hub_two: signal.hub_two,
+                expected_return: route_return(signal.margin, forward),
+                realized_return,
+                pnl,
+            });
+        }
+        peak = peak.max(cumulative);
+        max_drawdown = max_drawdown.max(peak - cumulative);
+
+        signals = logic::analyze(records, Some(epoch), params).opportunities;
+        signal_epoch = epoch;
+    }
+
+    let wins = trades.iter().filter(|t| t.pnl > 0.0).count();
+    let hit_rate = if trades.is_empty() {
+        0.0
+    } else {
+        wins as f64 / trades.len() as f64
+    };
+    BacktestReport {
+        summary: BacktestSummary {
+            params: params.clone(),
+            stake,
+            snapshots: snapshot_count,
+            first_epoch,
+            last_epoch,
+            trade_count: trades.len(),
+            unfilled,
+            total_pnl: cumulative,
+            hit_rate,
+            max_drawdown,
+        },
+        trades,
+    }
+}
+
+// Replays everything the source has
+pub fn backtest_source<S: MarketSource + ?Sized>(
+    source: &S,
+    params: &AnalysisParams,
+    stake: f64,
+) -> SourceResult<BacktestReport> {
+    Ok(backtest(source::history(source)?, params, stake))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pair_id: u64, one: (&str, f64), two: (&str, f64)) -> ExchangeRecord {
+        let mut record = ExchangeRecord {
+            pair_id,
+            volume: 20000.0,
+            ..Default::default()
+        };
+        record.currency_one.text = one.0.to_string();
+        record.currency_one_data.relative_price = one.1;
+        record.currency_two.text = two.0.to_string();
+        record.currency_two_data.relative_price = two.1;
+        record
+    }
+
+    // Divine at 300 ex and chaos at 12, plus each bridge's price in its
+    // divine pair and its exalt pair
+    fn snapshot(bridges: &[(&str, f64, f64)]) -> Vec<ExchangeRecord> {
+        let mut records = vec![
+            record(1, ("Divine Orb", 300.0), ("Exalted Orb", 1.0)),
+            record(2, ("Chaos Orb", 12.0), ("Exalted Orb", 1.0)),
+        ];
+        for (idx, &(bridge, in_divine, in_exalt)) in bridges.iter().enumerate() {
+            let pair_id = 10 + 2 * idx as u64;
+            records.push(record(pair_id, ("Divine Orb", 300.0), (bridge, in_divine)));
+            records.push(record(
+                pair_id + 1,
+                (bridge, in_exalt),
+                ("Exalted Orb", 1.0),
+            ));
+        }
+        records
+    }
+
+    #[test]
+    fn test_route_return_both_directions() {
+        assert!((route_return(0.1, true) - 0.1).abs() < 1e-9);
+        // 20% under the reference going forwards is 25% over it going back
+        assert!((route_return(-0.2, false) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fills_at_next_snapshot() {
+        let snapshots = vec![
+            // Vaal +10%
+            (1, snapshot(&[("Vaal Orb", 10.0, 11.0)])),
+            // Vaal +6%, Regal -20%, Chance +10% but it's about to vanish
+            (
+                2,
+                snapshot(&[
+                    ("Vaal Orb", 10.0, 10.6),
+                    ("Regal Orb", 10.0, 8.0),
+                    ("Orb of Chance", 10.0, 11.0),
+                ]),
+            ),
+            // Vaal -3%, Regal -10%
+            (
+                3,
+                snapshot(&[("Vaal Orb", 10.0, 9.7), ("Regal Orb", 10.0, 9.0)]),
+            ),
+        ];
+        let report = backtest(snapshots, &AnalysisParams::default(), 100.0);
+        let summary = &report.summary;
+
+        assert_eq!(summary.snapshots, 3);
+        assert_eq!(
+            (summary.first_epoch, summary.last_epoch),
+            (Some(1), Some(3))
+        );
+        assert_eq!(summary.unfilled, 1);
+        assert_eq!(summary.trade_count, 3);
+
+        let trades: Vec<(u64, &str, f64)> = report
+            .trades
+            .iter()
+            .map(|t| {
+                (
+                    t.executed_epoch,
+                    t.bridge.as_str(),
+                    (t.pnl * 100.0).round() / 100.0,
+                )
+            })
+            .collect();
+        // Regal was a signal to go the reverse way, which made 1/0.9 - 1
+        assert_eq!(
+            trades,
+            vec![
+                (2, "Vaal Orb", 6.0),
+                (3, "Vaal Orb", -3.0),
+                (3, "Regal Orb", 11.11)
+            ]
+        );
+        assert!((report.trades[2].expected_return - 0.25).abs() < 1e-9);
+
+        assert!((summary.total_pnl - 14.11).abs() < 0.01);
+        assert!((summary.hit_rate - 2.0 / 3.0).abs() < 1e-9);
+        // Both of step 3's trades land at once, so there's no dip
+        assert_eq!(summary.max_drawdown, 0.0);
+    }
+
+    #[test]
+    fn test_drawdown() {
+        let snapshots = vec![
+            (1, snapshot(&[("Vaal Orb", 10.0, 11.0)])),
+            (2, snapshot(&[("Vaal Orb", 10.0, 11.0)])),
+            (3, snapshot(&[("Vaal Orb", 10.0, 9.0)])),
+            (4, snapshot(&[])),
+        ];
+        let report = backtest(snapshots, &AnalysisParams::default(), 100.0);
+        // +10, then -10 (a peak of 10 down to 0), then the Vaal signal from
+        // snapshot 3 goes the reverse way and never fills
+        assert_eq!(report.summary.trade_count, 2);
+        assert_eq!(report.summary.unfilled, 1);
+        assert!((report.summary.max_drawdown - 10.0).abs() < 1e-9);
+    }
+}
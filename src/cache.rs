@@ -0,0 +1,754 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api;
+use crate::db;
+use crate::models::api_models::ExchangeRecord;
+use crate::source::MarketSource;
+
+pub mod prune;
+
+use prune::{PruneAction, RetentionPolicy};
+
+const INDEX_FILE_NAME: &str = "index.json";
+const SNAPSHOT_PREFIX: &str = "response_";
+const SNAPSHOT_SUFFIX: &str = ".json";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const TEMP_SUFFIX: &str = ".tmp";
+const CORRUPT_SUFFIX: &str = ".corrupt";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            // Level 19 is slow but we write a snapshot every so often and
+            // read it a lot, and the VPS disk is the thing we're short on
+            Compression::Zstd => zstd::encode_all(bytes, 19),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheLoadError {
+    NotCached(u64),
+    Io(io::Error),
+    // The file is there but it's not what we wrote: truncated, hash doesn't
+    // match, doesn't deserialize, etc.
+    Corrupt(String),
+}
+
+impl fmt::Display for CacheLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheLoadError::NotCached(epoch) => write!(f, "snapshot {} is not in the cache", epoch),
+            CacheLoadError::Io(e) => write!(f, "couldn't read cached snapshot: {}", e),
+            CacheLoadError::Corrupt(reason) => write!(f, "cached snapshot is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CacheLoadError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub epoch: u64,
+    pub league: String,
+    // Unix seconds of when we pulled this from the api
+    pub fetched_at: u64,
+    pub record_count: usize,
+    // sha256 of the file contents, hex encoded
+    pub content_hash: String,
+    pub file_name: String,
+    #[serde(default)]
+    pub size_bytes: u64,
+    // Set once the snapshot has been written to the database, which makes it
+    // fair game for pruning
+    #[serde(default)]
+    pub imported: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheIndex {
+    // Always kept sorted by epoch, oldest first
+    pub entries: Vec<CacheEntry>,
+}
+
+// The on-disk snapshot cache. Every snapshot is one json file in the data
+// directory and index.json keeps track of what's in there so we don't have to
+// go digging through file names every time.
+pub struct SnapshotCache {
+    dir: PathBuf,
+    index: CacheIndex,
+    // Only affects new writes, we can always read every format
+    compression: Compression,
+}
+
+impl SnapshotCache {
+    pub fn open(dir: &Path) -> Result<SnapshotCache, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let index = match read_index(&dir.join(INDEX_FILE_NAME)) {
+            Some(index) => index,
+            None => {
+                // No index yet (or it's busted), rebuild it from whatever
+                // snapshots are already sitting in the directory.
+                let index = rebuild_index(dir)?;
+                write_index(dir, &index)?;
+                index
+            }
+        };
+        Ok(SnapshotCache {
+            dir: dir.to_path_buf(),
+            index,
+            compression: Compression::None,
+        })
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    pub fn entries(&self) -> &[CacheEntry] {
+        &self.index.entries
+    }
+
+    pub fn get(&self, epoch: u64) -> Option<&CacheEntry> {
+        self.index
+            .entries
+            .binary_search_by_key(&epoch, |e| e.epoch)
+            .ok()
+            .map(|idx| &self.index.entries[idx])
+    }
+
+    pub fn contains(&self, epoch: u64) -> bool {
+        self.get(epoch).is_some()
+    }
+
+    pub fn newest(&self) -> Option<&CacheEntry> {
+        self.index.entries.last()
+    }
+
+    pub fn load(&self, epoch: u64) -> Result<Vec<ExchangeRecord>, CacheLoadError> {
+        let entry = self.get(epoch).ok_or(CacheLoadError::NotCached(epoch))?;
+        let bytes = match fs::read(self.dir.join(&entry.file_name)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(CacheLoadError::Corrupt(format!(
+                    "{} is missing",
+                    entry.file_name
+                )));
+            }
+            Err(e) => return Err(CacheLoadError::Io(e)),
+        };
+        if content_hash(&bytes) != entry.content_hash {
+            return Err(CacheLoadError::Corrupt(format!(
+                "{} doesn't match its hash in the index",
+                entry.file_name
+            )));
+        }
+        let json = decode_snapshot(&bytes).map_err(|e| CacheLoadError::Corrupt(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| CacheLoadError::Corrupt(e.to_string()))
+    }
+
+    // Move a bad snapshot out of the way and forget about it so the next
+    // lookup misses and we go fetch it again.
+    pub fn quarantine(&mut self, epoch: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let Ok(idx) = self.index.entries.binary_search_by_key(&epoch, |e| e.epoch) else {
+            return Ok(());
+        };
+        let entry = self.index.entries.remove(idx);
+        move_aside(&self.dir.join(&entry.file_name))?;
+        write_index(&self.dir, &self.index)
+    }
+
+    pub fn store(
+        &mut self,
+        epoch: u64,
+        league: &str,
+        records: &[ExchangeRecord],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file_name = snapshot_file_name(epoch, self.compression);
+        let bytes = self.compression.compress(&serde_json::to_vec(records)?)?;
+        cache_to_disk(&bytes, &self.dir, &file_name)?;
+
+        // If we already had this epoch in a different format, don't leave the
+        // old copy lying around
+        if let Some(old) = self.get(epoch)
+            && old.file_name != file_name
+        {
+            let _ = fs::remove_file(self.dir.join(&old.file_name));
+        }
+
+        self.insert_entry(CacheEntry {
+            epoch,
+            league: league.to_string(),
+            fetched_at: now_secs(),
+            record_count: records.len(),
+            content_hash: content_hash(&bytes),
+            file_name,
+            size_bytes: bytes.len() as u64,
+            imported: false,
+        });
+        write_index(&self.dir, &self.index)
+    }
+
+    // Flag a snapshot as being in the database
+    pub fn mark_imported(&mut self, epoch: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let Ok(idx) = self.index.entries.binary_search_by_key(&epoch, |e| e.epoch) else {
+            return Ok(());
+        };
+        self.index.entries[idx].imported = true;
+        write_index(&self.dir, &self.index)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.index.entries.iter().map(|e| e.size_bytes).sum()
+    }
+
+    pub fn prune(
+        &mut self,
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<Vec<PruneAction>, Box<dyn std::error::Error>> {
+        let actions = prune::plan_prune(&self.index.entries, policy, now_secs());
+        if dry_run || actions.is_empty() {
+            return Ok(actions);
+        }
+        for action in &actions {
+            match fs::remove_file(self.dir.join(&action.file_name)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.index
+            .entries
+            .retain(|e| !actions.iter().any(|a| a.epoch == e.epoch));
+        write_index(&self.dir, &self.index)?;
+        Ok(actions)
+    }
+
+    fn insert_entry(&mut self, entry: CacheEntry) {
+        match self
+            .index
+            .entries
+            .binary_search_by_key(&entry.epoch, |e| e.epoch)
+        {
+            Ok(idx) => self.index.entries[idx] = entry,
+            Err(idx) => self.index.entries.insert(idx, entry),
+        }
+    }
+}
+
+pub fn snapshot_file_name(epoch: u64, compression: Compression) -> String {
+    format!(
+        "{}{}{}{}",
+        SNAPSHOT_PREFIX,
+        epoch,
+        SNAPSHOT_SUFFIX,
+        compression.extension()
+    )
+}
+
+pub fn parse_snapshot_file_name(file_name: &str) -> Option<u64> {
+    let without_compression = [Compression::Gzip, Compression::Zstd]
+        .iter()
+        .find_map(|c| file_name.strip_suffix(c.extension()))
+        .unwrap_or(file_name);
+    without_compression
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_SUFFIX)?
+        .parse::<u64>()
+        .ok()
+}
+
+// Use the cached copy of a snapshot if we have a good one, otherwise pull the
+// newest pairs and cache them so we don't get banned from the api
+pub fn get_freshest_data<S: MarketSource + ?Sized>(
+    most_recent_epoch: u64,
+    cache: &mut SnapshotCache,
+    source: &S,
+) -> Result<Vec<ExchangeRecord>, Box<dyn std::error::Error>> {
+    if cache.contains(most_recent_epoch) {
+        match cache.load(most_recent_epoch) {
+            Ok(records) => {
+                eprintln!(
+                    "We have the most recent snapshot, number {}",
+                    &most_recent_epoch
+                );
+                return Ok(records);
+            }
+            Err(CacheLoadError::Corrupt(reason)) => {
+                eprintln!(
+                    "Cached snapshot {} is corrupt ({}), moving it aside and refetching",
+                    &most_recent_epoch, reason
+                );
+                cache.quarantine(most_recent_epoch)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    eprintln!("We do not have the most recent snapshot, getting newest pairs");
+    let fresh_data = source.pairs(most_recent_epoch)?;
+    cache.store(most_recent_epoch, api::LEAGUE, &fresh_data)?;
+    Ok(fresh_data)
+}
+
+// Copy a snapshot's pairs into the database's exchange_rates table and mark
+// it imported, which is what lets the retention policy drop it later. Does
+// nothing if it's already in there.
+pub fn import_snapshot(
+    cache: &mut SnapshotCache,
+    database_path: &Path,
+    epoch: u64,
+    records: &[ExchangeRecord],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cache.get(epoch).is_some_and(|e| e.imported) {
+        return Ok(());
+    }
+    db::record_snapshot(database_path, records)?;
+    cache.mark_imported(epoch)
+}
+
+// For --offline and --snapshot: a cached snapshot, the newest one if there's
+// no epoch. There's no refetching when we're offline, so a bad file is just
+// an error.
+pub fn load_cached(
+    cache: &SnapshotCache,
+    epoch: Option<u64>,
+) -> Result<(u64, Vec<ExchangeRecord>), Box<dyn std::error::Error>> {
+    let entry = match epoch {
+        Some(epoch) => cache.get(epoch).ok_or(CacheLoadError::NotCached(epoch))?,
+        None => cache
+            .newest()
+            .ok_or("the cache is empty, run once without --offline first")?,
+    };
+    eprintln!(
+        "WARNING: running offline on snapshot {}, fetched {} ago",
+        entry.epoch,
+        format_age(now_secs().saturating_sub(entry.fetched_at))
+    );
+    Ok((entry.epoch, cache.load(entry.epoch)?))
+}
+
+fn format_age(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, (secs % 86400) / 3600, (secs % 3600) / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+// What `cache list` prints
+pub fn write_listing(out: &mut impl Write, cache: &SnapshotCache) -> io::Result<()> {
+    for entry in cache.entries() {
+        writeln!(
+            out,
+            "{} | {} | fetched {} | {} records | {:.1} MB{}",
+            entry.epoch,
+            entry.league,
+            entry.fetched_at,
+            entry.record_count,
+            mb(entry.size_bytes),
+            if entry.imported { " | imported" } else { "" }
+        )?;
+    }
+    writeln!(
+        out,
+        "{} snapshots, {:.1} MB total",
+        cache.entries().len(),
+        mb(cache.total_size())
+    )
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+// For snapshots that aren't in the cache, like one a teammate sent over
+pub fn read_snapshot_file(path: &Path) -> Result<Vec<ExchangeRecord>, Box<dyn std::error::Error>> {
+    let bytes = fs::read(path)?;
+    Ok(serde_json::from_slice(&decode_snapshot(&bytes)?)?)
+}
+
+// Figure out what we're looking at from the first few bytes rather than the
+// file name, so plain and compressed snapshots can sit side by side.
+pub fn decode_snapshot(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        let mut out = Vec::new();
+        io::Read::read_to_end(&mut flate2::read::GzDecoder::new(bytes), &mut out)?;
+        Ok(out)
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(bytes)
+    } else {
+        Ok(bytes.to_vec())
+    }
+}
+
+// Write to a temp file next to the real one and rename it into place, so if
+// we get killed halfway through we never leave a truncated snapshot behind.
+pub fn cache_to_disk(
+    bytes: &[u8],
+    path_dir: &Path,
+    filename: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_path = path_dir.join(filename);
+    let temp_path = path_dir.join(format!("{}{}", filename, TEMP_SUFFIX));
+    let file = File::create(&temp_path)?;
+    let mut writer = BufWriter::new(file);
+
+    writer.write_all(bytes)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&temp_path, &file_path)?;
+    Ok(())
+}
+
+fn move_aside(file_path: &Path) -> io::Result<()> {
+    if !file_path.exists() {
+        return Ok(());
+    }
+    let mut corrupt_name = file_path.as_os_str().to_owned();
+    corrupt_name.push(format!(".{}{}", now_secs(), CORRUPT_SUFFIX));
+    fs::rename(file_path, corrupt_name)
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn read_index(index_path: &Path) -> Option<CacheIndex> {
+    let file = File::open(index_path).ok()?;
+    let mut index: CacheIndex = serde_json::from_reader(BufReader::new(file)).ok()?;
+    index.entries.sort_by_key(|e| e.epoch);
+    Some(index)
+}
+
+fn write_index(dir: &Path, index: &CacheIndex) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = serde_json::to_vec_pretty(index)?;
+    cache_to_disk(&bytes, dir, INDEX_FILE_NAME)
+}
+
+fn rebuild_index(dir: &Path) -> Result<CacheIndex, Box<dyn std::error::Error>> {
+    let mut index = CacheIndex::default();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_type()?.is_file() {
+            continue;
+        }
+        // Anything that isn't one of our snapshots just gets skipped
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        let Some(epoch) = parse_snapshot_file_name(&file_name) else {
+            continue;
+        };
+        let bytes = fs::read(dir_entry.path())?;
+        let Ok(records) = decode_snapshot(&bytes)
+            .map_err(serde_json::Error::io)
+            .and_then(|json| serde_json::from_slice::<Vec<ExchangeRecord>>(&json))
+        else {
+            eprintln!("Moving aside unreadable snapshot {}", file_name);
+            move_aside(&dir_entry.path())?;
+            continue;
+        };
+        let fetched_at = dir_entry
+            .metadata()?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        index.entries.push(CacheEntry {
+            epoch,
+            // Old snapshots didn't record this, they're all from the one
+            // league we've been pointing at
+            league: api::LEAGUE.to_string(),
+            fetched_at,
+            record_count: records.len(),
+            content_hash: content_hash(&bytes),
+            file_name,
+            size_bytes: bytes.len() as u64,
+            imported: false,
+        });
+    }
+    index.entries.sort_by_key(|e| e.epoch);
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "poe_flip_finder_cache_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_parse_snapshot_file_name() {
+        assert_eq!(parse_snapshot_file_name("response_1234.json"), Some(1234));
+        assert_eq!(parse_snapshot_file_name("response_abc.json"), None);
+        assert_eq!(parse_snapshot_file_name("notes.txt"), None);
+        assert_eq!(parse_snapshot_file_name("index.json"), None);
+        assert_eq!(parse_snapshot_file_name("response_12.json.bak"), None);
+        assert_eq!(parse_snapshot_file_name("response_12.json.gz"), Some(12));
+        assert_eq!(parse_snapshot_file_name("response_12.json.zst"), Some(12));
+        assert_eq!(parse_snapshot_file_name("response_12.gz"), None);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let dir = temp_dir(&format!("compressed_{:?}", compression));
+            let mut cache = SnapshotCache::open(&dir).unwrap();
+            cache.set_compression(compression);
+            let records: Vec<ExchangeRecord> = (0..50).map(|_| ExchangeRecord::default()).collect();
+            cache.store(11, "Standard", &records).unwrap();
+
+            let file_name = snapshot_file_name(11, compression);
+            assert!(dir.join(&file_name).exists());
+            let plain_size = serde_json::to_vec(&records).unwrap().len() as u64;
+            assert!(cache.get(11).unwrap().size_bytes < plain_size);
+
+            // Rebuilding the index from scratch has to read it too
+            fs::remove_file(dir.join(INDEX_FILE_NAME)).unwrap();
+            let reopened = SnapshotCache::open(&dir).unwrap();
+            assert_eq!(reopened.get(11).unwrap().file_name, file_name);
+            assert_eq!(reopened.load(11).unwrap().len(), 50);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reads_plain_and_compressed_side_by_side() {
+        let dir = temp_dir("mixed");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        cache
+            .store(1, "Standard", &[ExchangeRecord::default()])
+            .unwrap();
+        cache.set_compression(Compression::Zstd);
+        cache
+            .store(2, "Standard", &[ExchangeRecord::default()])
+            .unwrap();
+
+        assert_eq!(cache.get(1).unwrap().file_name, "response_1.json");
+        assert_eq!(cache.get(2).unwrap().file_name, "response_2.json.zst");
+        assert_eq!(cache.load(1).unwrap().len(), 1);
+        assert_eq!(cache.load(2).unwrap().len(), 1);
+
+        // Re-storing in the new format replaces the old plain file
+        cache
+            .store(1, "Standard", &[ExchangeRecord::default()])
+            .unwrap();
+        assert!(!dir.join("response_1.json").exists());
+        assert!(dir.join("response_1.json.zst").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_and_load_round_trip() {
+        let dir = temp_dir("round_trip");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        let records = vec![ExchangeRecord::default(), ExchangeRecord::default()];
+        cache.store(42, "Standard", &records).unwrap();
+
+        let reopened = SnapshotCache::open(&dir).unwrap();
+        let entry = reopened.get(42).unwrap();
+        assert_eq!(entry.record_count, 2);
+        assert_eq!(entry.league, "Standard");
+        assert_eq!(entry.content_hash.len(), 64);
+        assert_eq!(reopened.load(42).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_ignores_foreign_files() {
+        let dir = temp_dir("foreign");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("response_7.json"), "[]").unwrap();
+        fs::write(dir.join("response_9.json"), "[]").unwrap();
+        fs::write(dir.join("notes.txt"), "hello").unwrap();
+        fs::write(dir.join("scratch.json"), "{}").unwrap();
+        fs::create_dir_all(dir.join("old")).unwrap();
+
+        let cache = SnapshotCache::open(&dir).unwrap();
+        let epochs: Vec<u64> = cache.entries().iter().map(|e| e.epoch).collect();
+        assert_eq!(epochs, vec![7, 9]);
+        assert_eq!(cache.newest().unwrap().epoch, 9);
+        assert!(!cache.contains(8));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_temp_file_left_behind() {
+        let dir = temp_dir("temp_file");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        cache.store(1, "Standard", &[]).unwrap();
+        let names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().all(|n| !n.ends_with(TEMP_SUFFIX)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_snapshot_is_corrupt() {
+        let dir = temp_dir("truncated");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        cache
+            .store(3, "Standard", &[ExchangeRecord::default()])
+            .unwrap();
+        fs::write(
+            dir.join(snapshot_file_name(3, Compression::None)),
+            "[{\"CurrencyExch",
+        )
+        .unwrap();
+
+        assert!(matches!(cache.load(3), Err(CacheLoadError::Corrupt(_))));
+        cache.quarantine(3).unwrap();
+        assert!(!cache.contains(3));
+        assert!(!dir.join(snapshot_file_name(3, Compression::None)).exists());
+        assert!(matches!(cache.load(3), Err(CacheLoadError::NotCached(3))));
+
+        // and it stays gone after reopening
+        let reopened = SnapshotCache::open(&dir).unwrap();
+        assert!(!reopened.contains(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_moves_unreadable_snapshot_aside() {
+        let dir = temp_dir("rebuild_corrupt");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("response_5.json"), "[{").unwrap();
+
+        let cache = SnapshotCache::open(&dir).unwrap();
+        assert!(cache.entries().is_empty());
+        assert!(!dir.join("response_5.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_freshest_data_refetches_corrupt_snapshot() {
+        let dir = temp_dir("freshest");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        let mut source = MemorySource::new();
+        source.insert(6, vec![ExchangeRecord::default(); 3]);
+
+        // Not cached yet, so it comes from the source and gets stored
+        assert_eq!(get_freshest_data(6, &mut cache, &source).unwrap().len(), 3);
+        assert_eq!(cache.get(6).unwrap().record_count, 3);
+
+        fs::write(dir.join(snapshot_file_name(6, Compression::None)), "[{").unwrap();
+        assert_eq!(get_freshest_data(6, &mut cache, &source).unwrap().len(), 3);
+        assert_eq!(cache.load(6).unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(59), "0m");
+        assert_eq!(format_age(3 * 60), "3m");
+        assert_eq!(format_age(2 * 3600 + 5 * 60), "2h 5m");
+        assert_eq!(format_age(3 * 86400 + 4 * 3600 + 59), "3d 4h");
+    }
+
+    #[test]
+    fn test_load_cached() {
+        let dir = temp_dir("load_cached");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        assert!(load_cached(&cache, None).is_err());
+        cache
+            .store(4, "Standard", &[ExchangeRecord::default()])
+            .unwrap();
+        cache.store(5, "Standard", &[]).unwrap();
+
+        assert_eq!(load_cached(&cache, None).unwrap().0, 5);
+        let (epoch, records) = load_cached(&cache, Some(4)).unwrap();
+        assert_eq!((epoch, records.len()), (4, 1));
+        assert!(load_cached(&cache, Some(6)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_snapshot_marks_it_imported() {
+        let dir = temp_dir("import");
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        // poe2scout's snapshot id isn't the epoch
+        let record = ExchangeRecord {
+            snapshot_id: 4321,
+            ..Default::default()
+        };
+        let records = vec![record; 2];
+        cache.store(8, "Standard", &records).unwrap();
+        assert!(!cache.get(8).unwrap().imported);
+
+        let db_path = dir.join("flips.db");
+        import_snapshot(&mut cache, &db_path, 8, &records).unwrap();
+        assert!(cache.get(8).unwrap().imported);
+        // Already in there, so a second go doesn't add anything
+        import_snapshot(&mut cache, &db_path, 8, &records).unwrap();
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        assert_eq!(db::get_all_entries(&conn).unwrap().len(), 2);
+        assert!(SnapshotCache::open(&dir).unwrap().get(8).unwrap().imported);
+
+        // A rebuilt index has forgotten the import, so it happens again but
+        // still leaves just the one copy
+        fs::remove_file(dir.join(INDEX_FILE_NAME)).unwrap();
+        let mut cache = SnapshotCache::open(&dir).unwrap();
+        assert!(!cache.get(8).unwrap().imported);
+        import_snapshot(&mut cache, &db_path, 8, &records).unwrap();
+        assert_eq!(db::get_all_entries(&conn).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
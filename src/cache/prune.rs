@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+
+use serde::Deserialize;
+
+use crate::cache::{CacheEntry, mb};
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+
+// Keep everything recent, thin out to one snapshot an hour after that and one
+// a day once things get old. On top of that we can cap the size on disk.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub keep_all_hours: u64,
+    pub hourly_days: u64,
+    pub max_size_mb: Option<u64>,
+    // Snapshots we've already put in the database can go once they're out of
+    // the keep everything window
+    pub delete_imported: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_all_hours: 48,
+            hourly_days: 30,
+            max_size_mb: None,
+            delete_imported: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneReason {
+    HourlyDuplicate,
+    DailyDuplicate,
+    Imported,
+    OverSizeLimit,
+}
+
+impl fmt::Display for PruneReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PruneReason::HourlyDuplicate => write!(f, "already have a snapshot for that hour"),
+            PruneReason::DailyDuplicate => write!(f, "already have a snapshot for that day"),
+            PruneReason::Imported => write!(f, "already imported into the database"),
+            PruneReason::OverSizeLimit => write!(f, "cache is over its size limit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PruneAction {
+    pub epoch: u64,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub reason: PruneReason,
+}
+
+// Works out what to delete, doesn't touch the disk. entries should be sorted
+// oldest first like they are in the index. The newest snapshot is never
+// removed, we always want something to analyze offline.
+pub fn plan_prune(entries: &[CacheEntry], policy: &RetentionPolicy, now: u64) -> Vec<PruneAction> {
+    let mut actions = Vec::new();
+    let mut kept: Vec<&CacheEntry> = Vec::new();
+    let mut seen_hours = HashSet::new();
+    let mut seen_days = HashSet::new();
+
+    let keep_all_secs = policy.keep_all_hours * HOUR_SECS;
+    let hourly_secs = policy.hourly_days * DAY_SECS;
+
+    for (idx, entry) in entries.iter().enumerate().rev() {
+        let is_newest = idx + 1 == entries.len();
+        let age = now.saturating_sub(entry.fetched_at);
+
+        let reason = if is_newest || age <= keep_all_secs {
+            None
+        } else if policy.delete_imported && entry.imported {
+            Some(PruneReason::Imported)
+        } else if age <= hourly_secs {
+            // Walking newest to oldest, so the first one we see in each
+            // bucket is the one we keep
+            (!seen_hours.insert(entry.fetched_at / HOUR_SECS))
+                .then_some(PruneReason::HourlyDuplicate)
+        } else {
+            (!seen_days.insert(entry.fetched_at / DAY_SECS)).then_some(PruneReason::DailyDuplicate)
+        };
+
+        match reason {
+            Some(reason) => actions.push(prune_action(entry, reason)),
+            None => kept.push(entry),
+        }
+    }
+
+    if let Some(max_size_mb) = policy.max_size_mb {
+        let max_bytes = max_size_mb * 1024 * 1024;
+        let mut total: u64 = kept.iter().map(|e| e.size_bytes).sum();
+        // kept is newest first, so pop the oldest off the back until we fit
+        while total > max_bytes && kept.len() > 1 {
+            let entry = kept.pop().unwrap();
+            total -= entry.size_bytes;
+            actions.push(prune_action(entry, PruneReason::OverSizeLimit));
+        }
+    }
+
+    actions.sort_by_key(|a| a.epoch);
+    actions
+}
+
+// What `cache prune` (and auto prune) prints. A dry run didn't delete
+// anything so it says what it would have done instead.
+pub fn write_prune_actions(
+    out: &mut impl Write,
+    actions: &[PruneAction],
+    dry_run: bool,
+) -> io::Result<()> {
+    if actions.is_empty() {
+        return writeln!(out, "Nothing to prune");
+    }
+    let (removed, freed) = if dry_run {
+        ("Would remove", "Would free")
+    } else {
+        ("Removed", "Freed")
+    };
+    for action in actions {
+        writeln!(
+            out,
+            "{} {} ({:.1} MB): {}",
+            removed,
+            action.file_name,
+            mb(action.size_bytes),
+            action.reason
+        )?;
+    }
+    let total: u64 = actions.iter().map(|a| a.size_bytes).sum();
+    writeln!(
+        out,
+        "{} {:.1} MB from {} snapshots",
+        freed,
+        mb(total),
+        actions.len()
+    )
+}
+
+fn prune_action(entry: &CacheEntry, reason: PruneReason) -> PruneAction {
+    PruneAction {
+        epoch: entry.epoch,
+        file_name: entry.file_name.clone(),
+        size_bytes: entry.size_bytes,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 100 * DAY_SECS;
+
+    fn entry(epoch: u64, age_secs: u64) -> CacheEntry {
+        CacheEntry {
+            epoch,
+            league: "Standard".to_string(),
+            fetched_at: NOW - age_secs,
+            record_count: 0,
+            content_hash: String::new(),
+            file_name: format!("response_{}.json", epoch),
+            size_bytes: 1024 * 1024,
+            imported: false,
+        }
+    }
+
+    fn pruned_epochs(actions: &[PruneAction]) -> Vec<u64> {
+        actions.iter().map(|a| a.epoch).collect()
+    }
+
+    #[test]
+    fn test_keeps_everything_recent() {
+        let entries: Vec<CacheEntry> = (0..10).map(|i| entry(i, (10 - i) * 60)).collect();
+        assert!(plan_prune(&entries, &RetentionPolicy::default(), NOW).is_empty());
+    }
+
+    #[test]
+    fn test_thins_to_hourly_then_daily() {
+        let entries = vec![
+            // two on the same day, 40 days ago
+            entry(1, 40 * DAY_SECS + 2 * HOUR_SECS),
+            entry(2, 40 * DAY_SECS + HOUR_SECS),
+            // two in the same hour, 3 days ago
+            entry(3, 3 * DAY_SECS + 20 * 60),
+            entry(4, 3 * DAY_SECS + 10 * 60),
+            // recent
+            entry(5, 60),
+        ];
+        let actions = plan_prune(&entries, &RetentionPolicy::default(), NOW);
+        assert_eq!(pruned_epochs(&actions), vec![1, 3]);
+        assert_eq!(actions[0].reason, PruneReason::DailyDuplicate);
+        assert_eq!(actions[1].reason, PruneReason::HourlyDuplicate);
+    }
+
+    #[test]
+    fn test_size_limit_drops_oldest_but_never_newest() {
+        let entries: Vec<CacheEntry> = (0..4).map(|i| entry(i, (4 - i) * 60)).collect();
+        let policy = RetentionPolicy {
+            max_size_mb: Some(2),
+            ..Default::default()
+        };
+        let actions = plan_prune(&entries, &policy, NOW);
+        assert_eq!(pruned_epochs(&actions), vec![0, 1]);
+        assert!(
+            actions
+                .iter()
+                .all(|a| a.reason == PruneReason::OverSizeLimit)
+        );
+
+        let tiny = RetentionPolicy {
+            max_size_mb: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            pruned_epochs(&plan_prune(&entries, &tiny, NOW)),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_imported_only_deleted_when_allowed() {
+        let mut old = entry(1, 5 * DAY_SECS);
+        old.imported = true;
+        let entries = vec![old, entry(2, 60)];
+        assert!(plan_prune(&entries, &RetentionPolicy::default(), NOW).is_empty());
+
+        let policy = RetentionPolicy {
+            delete_imported: true,
+            ..Default::default()
+        };
+        let actions = plan_prune(&entries, &policy, NOW);
+        assert_eq!(pruned_epochs(&actions), vec![1]);
+        assert_eq!(actions[0].reason, PruneReason::Imported);
+    }
+
+    #[test]
+    fn test_dry_run_says_would() {
+        let actions = vec![prune_action(&entry(1, 0), PruneReason::OverSizeLimit)];
+        let mut out = Vec::new();
+        write_prune_actions(&mut out, &actions, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Would remove response_1.json (1.0 MB): cache is over its size limit\n\
+            Would free 1.0 MB from 1 snapshots\n"
+        );
+
+        let mut out = Vec::new();
+        write_prune_actions(&mut out, &actions, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Removed response_1.json"));
+        assert!(out.contains("Freed 1.0 MB"));
+    }
+}
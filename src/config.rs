@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::alert::AlertFormat;
+use crate::cache::Compression;
+use crate::cache::prune::RetentionPolicy;
+
+// Everything in here has a default so the config file is optional and can
+// be as small as the one setting you care about.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub database_path: PathBuf,
+    // Save every opportunity we find to the database
+    pub record_opportunities: bool,
+    // Copy every snapshot we fetch into the database's exchange_rates table,
+    // which is what export --from/--to and backtest --db read
+    pub record_snapshots: bool,
+    pub cache: CacheConfig,
+    pub watch: WatchConfig,
+    pub alert: AlertConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("data"),
+            database_path: PathBuf::from("flips.db"),
+            record_opportunities: true,
+            record_snapshots: true,
+            cache: CacheConfig::default(),
+            watch: WatchConfig::default(),
+            alert: AlertConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    // Prune the cache after every new snapshot we store
+    pub auto_prune: bool,
+    // How new snapshots get written, old ones are read whatever they are
+    pub compression: Compression,
+    pub retention: RetentionPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+    // How often to ask poe2scout for a new snapshot
+    pub interval_secs: u64,
+    // First retry after an error, doubles every failure after that
+    pub retry_secs: u64,
+    pub max_backoff_secs: u64,
+    // How far a margin has to move (as a fraction, 0.005 is half a percent)
+    // before we call an opportunity changed
+    pub change_threshold: f64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            interval_secs: 300,
+            retry_secs: 30,
+            max_backoff_secs: 1800,
+            change_threshold: 0.005,
+        }
+    }
+}
+
+// Webhook alerts from watch mode, off unless there's a url
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub webhook_url: Option<String>,
+    pub format: AlertFormat,
+    // Alert when a route's margin gets at least this far from zero, either
+    // direction
+    pub min_margin: f64,
+    // Don't alert about the same route again for this long
+    pub cooldown_secs: u64,
+    pub retries: u32,
+    pub retry_secs: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            webhook_url: None,
+            format: AlertFormat::Discord,
+            min_margin: 0.1,
+            cooldown_secs: 3600,
+            retries: 3,
+            retry_secs: 5,
+        }
+    }
+}
+
+pub fn load_config(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let contents = fs::read_to_string(path)?;
+    Ok(toml::from_str(&contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config_is_default() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("data"));
+        assert!(!config.cache.auto_prune);
+        assert_eq!(config.cache.compression, Compression::None);
+        assert_eq!(config.cache.retention.keep_all_hours, 48);
+        assert_eq!(config.watch.interval_secs, 300);
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let config: Config = toml::from_str(
+            "data_dir = \"snapshots\"\n[cache]\nauto_prune = true\ncompression = \"zstd\"\n[cache.retention]\nmax_size_mb = 200\n",
+        )
+        .unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("snapshots"));
+        assert!(config.cache.auto_prune);
+        assert_eq!(config.cache.compression, Compression::Zstd);
+        assert_eq!(config.cache.retention.max_size_mb, Some(200));
+        assert_eq!(config.cache.retention.hourly_days, 30);
+    }
+
+    #[test]
+    fn test_alert_config() {
+        let config: Config = toml::from_str(
+            "[alert]\nwebhook_url = \"http://localhost:9000/hook\"\nformat = \"generic\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.alert.webhook_url.as_deref(),
+            Some("http://localhost:9000/hook")
+        );
+        assert_eq!(config.alert.format, AlertFormat::Generic);
+        assert_eq!(config.alert.cooldown_secs, 3600);
+    }
+}
@@ -1,12 +1,16 @@
+//! SQLite storage: the raw `exchange_rates` rows and the `opportunities`
+//! we found in them.
+
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, Result, Row, Transaction};
 
-use crate::models::{ExchangeQueryResult, ExchangeRecord};
+use crate::models::api_models::{ExchangeQueryResult, ExchangeRecord};
+use crate::models::logic_models::{Direction, Opportunity, TradingCurrencyType};
 
-pub fn new_schema(conn: &Connection) -> Result<()> {
-    let schema = "DROP TABLE IF EXISTS exchange_rates;
-    CREATE TABLE IF NOT EXISTS exchange_rates (
+const EXCHANGE_RATES_TABLE: &str = "CREATE TABLE IF NOT EXISTS exchange_rates (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     timestamp INTEGER NOT NULL,
     pair_id INTEGER NOT NULL,
@@ -17,40 +21,69 @@ pub fn new_schema(conn: &Connection) -> Result<()> {
     to_relative_price REAL,
     volume REAL)";
 
-    conn.execute_batch(schema)?;
-    Ok(())
+/// Creates the `exchange_rates` table, dropping whatever was there
+pub fn new_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS exchange_rates;")?;
+    conn.execute_batch(EXCHANGE_RATES_TABLE)
+}
+
+/// Creates the `exchange_rates` table if it's missing, keeping what's in it
+pub fn ensure_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(EXCHANGE_RATES_TABLE)
 }
 
+/// Stores one snapshot's pairs, stamped with the current time
 pub fn insert_all_rows(records: &[ExchangeRecord], conn: &mut Connection) -> Result<()> {
     let tx = conn.transaction()?;
-    {
-        let mut insert_statement = tx.prepare("INSERT INTO exchange_rates
-        (timestamp, pair_id, snapshot_id, from_currency, to_currency, from_relative_price, to_relative_price, volume)
-        VALUES
-        (:ts, :pair_id, :snapshot_id, :from_currency, :to_currency, :from_relative_price, :to_relative_price, :volume)")?;
+    insert_rows(&tx, records)?;
+    tx.commit()
+}
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+/// Like [`insert_all_rows`] but first drops whatever rows the records'
+/// snapshots already had, so importing one twice doesn't double it up
+pub fn replace_snapshot_rows(conn: &mut Connection, records: &[ExchangeRecord]) -> Result<()> {
+    let mut snapshot_ids: Vec<u64> = records.iter().map(|r| r.snapshot_id).collect();
+    snapshot_ids.sort_unstable();
+    snapshot_ids.dedup();
 
-        for entry in records {
-            insert_statement.execute((
-                &now,
-                entry.pair_id,
-                entry.snapshot_id,
-                entry.currency_one.text.clone(),
-                entry.currency_two.text.clone(),
-                entry.currency_one_data.relative_price,
-                entry.currency_two_data.relative_price,
-                entry.volume,
-            ))?;
-        }
+    let tx = conn.transaction()?;
+    for snapshot_id in snapshot_ids {
+        tx.execute(
+            "DELETE FROM exchange_rates WHERE snapshot_id = ?1",
+            [snapshot_id],
+        )?;
+    }
+    insert_rows(&tx, records)?;
+    tx.commit()
+}
+
+fn insert_rows(tx: &Transaction, records: &[ExchangeRecord]) -> Result<()> {
+    let mut insert_statement = tx.prepare("INSERT INTO exchange_rates
+    (timestamp, pair_id, snapshot_id, from_currency, to_currency, from_relative_price, to_relative_price, volume)
+    VALUES
+    (:ts, :pair_id, :snapshot_id, :from_currency, :to_currency, :from_relative_price, :to_relative_price, :volume)")?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    for entry in records {
+        insert_statement.execute((
+            &now,
+            entry.pair_id,
+            entry.snapshot_id,
+            entry.currency_one.text.clone(),
+            entry.currency_two.text.clone(),
+            entry.currency_one_data.relative_price,
+            entry.currency_two_data.relative_price,
+            entry.volume,
+        ))?;
     }
-    tx.commit()?;
     Ok(())
 }
 
+/// Every row in `exchange_rates`, despite the name
 pub fn get_most_recent_entry(conn: &mut Connection) -> Vec<ExchangeQueryResult> {
     let mut query = conn.prepare("SELECT * FROM exchange_rates").unwrap();
     let elem_iter = query
@@ -71,3 +104,455 @@ pub fn get_most_recent_entry(conn: &mut Connection) -> Vec<ExchangeQueryResult>
         .collect::<Result<Vec<ExchangeQueryResult>>>()
         .expect("Couldn't collect mapped rows: ")
 }
+
+// The columns query_result_from_row expects, in its order
+const QUERY_RESULT_SELECT: &str =
+    "SELECT timestamp, pair_id, snapshot_id, from_currency, to_currency,
+    from_relative_price, to_relative_price, volume
+    FROM exchange_rates";
+
+fn query_result_from_row(row: &Row) -> Result<ExchangeQueryResult> {
+    Ok(ExchangeQueryResult {
+        ts: row.get(0)?,
+        pair_id: row.get(1)?,
+        snapshot_id: row.get(2)?,
+        from_currency: row.get(3)?,
+        to_currency: row.get(4)?,
+        from_relative_price: row.get(5)?,
+        to_relative_price: row.get(6)?,
+        volume: row.get(7)?,
+    })
+}
+
+/// Everything stored with a timestamp in [from_ts, to_ts], oldest first
+pub fn get_entries_between(
+    conn: &Connection,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<Vec<ExchangeQueryResult>> {
+    let mut query = conn.prepare(&format!(
+        "{} WHERE timestamp BETWEEN ?1 AND ?2 ORDER BY timestamp, snapshot_id, pair_id",
+        QUERY_RESULT_SELECT
+    ))?;
+    let elem_iter = query.query_map((from_ts, to_ts), query_result_from_row)?;
+    elem_iter.collect()
+}
+
+/// Every stored row, grouped by snapshot so they can be replayed in order
+pub fn get_all_entries(conn: &Connection) -> Result<Vec<ExchangeQueryResult>> {
+    let mut query = conn.prepare(&format!(
+        "{} ORDER BY snapshot_id, timestamp, pair_id",
+        QUERY_RESULT_SELECT
+    ))?;
+    let elem_iter = query.query_map([], query_result_from_row)?;
+    elem_iter.collect()
+}
+
+/// Whether `exchange_rates` is there and has anything in it
+pub fn has_snapshots(conn: &Connection) -> Result<bool> {
+    let table_exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'exchange_rates')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !table_exists {
+        return Ok(false);
+    }
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM exchange_rates)", [], |row| {
+        row.get(0)
+    })
+}
+
+/// Every snapshot id in `exchange_rates`, oldest first
+pub fn get_snapshot_ids(conn: &Connection) -> Result<Vec<u64>> {
+    let mut query =
+        conn.prepare("SELECT DISTINCT snapshot_id FROM exchange_rates ORDER BY snapshot_id")?;
+    let elem_iter = query.query_map([], |row| row.get(0))?;
+    elem_iter.collect()
+}
+
+/// The rows of one snapshot
+pub fn get_snapshot_entries(
+    conn: &Connection,
+    snapshot_id: u64,
+) -> Result<Vec<ExchangeQueryResult>> {
+    let mut query = conn.prepare(&format!(
+        "{} WHERE snapshot_id = ?1 ORDER BY timestamp, pair_id",
+        QUERY_RESULT_SELECT
+    ))?;
+    let elem_iter = query.query_map([snapshot_id], query_result_from_row)?;
+    elem_iter.collect()
+}
+
+/// Unlike new_schema this one never drops anything, it runs every time we
+/// record. Rerunning the same snapshot replaces its rows instead of doubling
+/// them up. Tables from before we kept both hub volumes get the columns added,
+/// their old rows only have `limiting_volume`. `analyzed_snapshots` keeps every
+/// epoch we've recorded, including the ones that found nothing.
+pub fn ensure_opportunity_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS opportunities (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        snapshot_epoch INTEGER NOT NULL,
+        recorded_at INTEGER NOT NULL,
+        hub_one TEXT NOT NULL,
+        bridge TEXT NOT NULL,
+        hub_two TEXT NOT NULL,
+        implied_rate REAL NOT NULL,
+        reference_rate REAL NOT NULL,
+        margin REAL NOT NULL,
+        limiting_volume REAL NOT NULL,
+        hub_one_volume REAL,
+        hub_two_volume REAL,
+        UNIQUE (snapshot_epoch, hub_one, bridge, hub_two));
+    CREATE INDEX IF NOT EXISTS opportunities_by_bridge ON opportunities (bridge, snapshot_epoch);
+    CREATE TABLE IF NOT EXISTS analyzed_snapshots (
+        snapshot_epoch INTEGER PRIMARY KEY,
+        analyzed_at INTEGER NOT NULL);",
+    )?;
+    let has_hub_volumes: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('opportunities') WHERE name = 'hub_one_volume')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_hub_volumes {
+        conn.execute_batch(
+            "ALTER TABLE opportunities ADD COLUMN hub_one_volume REAL;
+            ALTER TABLE opportunities ADD COLUMN hub_two_volume REAL;",
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes one snapshot's opportunities in a single transaction, replacing
+/// whatever that snapshot had before. A route the rerun doesn't find anymore
+/// is dropped with the rest. The snapshot goes in `analyzed_snapshots` even
+/// when there's nothing to write, so the history knows it was looked at.
+pub fn insert_opportunities(
+    conn: &mut Connection,
+    snapshot_epoch: u64,
+    opportunities: &[Opportunity],
+) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM opportunities WHERE snapshot_epoch = ?1",
+        [snapshot_epoch],
+    )?;
+    tx.execute(
+        "INSERT OR REPLACE INTO analyzed_snapshots (snapshot_epoch, analyzed_at) VALUES (?1, ?2)",
+        (snapshot_epoch, now),
+    )?;
+    {
+        let mut insert_statement = tx.prepare(
+            "INSERT OR REPLACE INTO opportunities
+        (snapshot_epoch, recorded_at, hub_one, bridge, hub_two, implied_rate, reference_rate, margin,
+        limiting_volume, hub_one_volume, hub_two_volume)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+
+        for opportunity in opportunities {
+            insert_statement.execute((
+                snapshot_epoch,
+                now,
+                opportunity.hub_one.to_string(),
+                &opportunity.bridge,
+                opportunity.hub_two.to_string(),
+                opportunity.implied_rate,
+                opportunity.reference_rate,
+                opportunity.margin,
+                opportunity.hub_one_volume.min(opportunity.hub_two_volume),
+                opportunity.hub_one_volume,
+                opportunity.hub_two_volume,
+            ))?;
+        }
+    }
+    tx.commit()
+}
+
+/// Everything recorded, oldest snapshot first. Rows from before we stored
+/// both hub volumes come back with the thinner leg's volume for both, and
+/// the direction comes from the margin's sign like it did when it was found.
+pub fn get_opportunities(conn: &Connection) -> Result<Vec<Opportunity>> {
+    let mut query = conn.prepare(
+        "SELECT snapshot_epoch, hub_one, bridge, hub_two, implied_rate, reference_rate,
+        margin, COALESCE(hub_one_volume, limiting_volume), COALESCE(hub_two_volume, limiting_volume)
+        FROM opportunities
+        ORDER BY snapshot_epoch, id",
+    )?;
+    let elem_iter = query.query_map([], |row| {
+        let hub_one: String = row.get(1)?;
+        let hub_two: String = row.get(3)?;
+        let margin = row.get(6)?;
+        Ok(Opportunity {
+            snapshot_epoch: row.get(0)?,
+            hub_one: hub_from_name(&hub_one),
+            bridge: row.get(2)?,
+            hub_two: hub_from_name(&hub_two),
+            implied_rate: row.get(4)?,
+            reference_rate: row.get(5)?,
+            margin,
+            direction: Direction::from_margin(margin),
+            hub_one_volume: row.get(7)?,
+            hub_two_volume: row.get(8)?,
+        })
+    })?;
+    elem_iter.collect()
+}
+
+/// [`get_opportunities`] split up by snapshot, oldest first. Snapshots that
+/// were analyzed but had no opportunities come back empty. Ones recorded
+/// before we kept `analyzed_snapshots` only show up if they found something.
+pub fn get_opportunity_history(conn: &Connection) -> Result<Vec<(u64, Vec<Opportunity>)>> {
+    let mut snapshots: BTreeMap<u64, Vec<Opportunity>> = BTreeMap::new();
+    let mut query = conn.prepare("SELECT snapshot_epoch FROM analyzed_snapshots")?;
+    for epoch in query.query_map([], |row| row.get(0))? {
+        snapshots.insert(epoch?, Vec::new());
+    }
+    for opportunity in get_opportunities(conn)? {
+        let epoch = opportunity.snapshot_epoch.unwrap_or_default();
+        snapshots.entry(epoch).or_default().push(opportunity);
+    }
+    Ok(snapshots.into_iter().collect())
+}
+
+// The hubs go in by their short display names, not the item names
+fn hub_from_name(name: &str) -> TradingCurrencyType {
+    match name {
+        "Exalt" => TradingCurrencyType::Exalt,
+        "Chaos" => TradingCurrencyType::Chaos,
+        "Divine" => TradingCurrencyType::Divine,
+        _ => TradingCurrencyType::Other,
+    }
+}
+
+/// Opens the database, makes sure the table is there and writes one
+/// snapshot's worth of opportunities
+pub fn record_opportunities(
+    path: &Path,
+    snapshot_epoch: u64,
+    opportunities: &[Opportunity],
+) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    ensure_opportunity_schema(&conn)?;
+    insert_opportunities(&mut conn, snapshot_epoch, opportunities)
+}
+
+/// Opens the database, makes sure `exchange_rates` is there and writes one
+/// snapshot's pairs into it
+pub fn record_snapshot(path: &Path, records: &[ExchangeRecord]) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    ensure_schema(&conn)?;
+    replace_snapshot_rows(&mut conn, records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rerunning_a_snapshot_replaces_its_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_opportunity_schema(&conn).unwrap();
+        let first = vec![
+            Opportunity::sample("Vaal Orb", 0.1),
+            Opportunity::sample("Regal Orb", -0.2),
+        ];
+        insert_opportunities(&mut conn, 5, &first).unwrap();
+        // Schema setup is safe to run again on a full table
+        ensure_opportunity_schema(&conn).unwrap();
+        // Regal Orb isn't there the second time round so its old row goes
+        insert_opportunities(&mut conn, 5, &[Opportunity::sample("Vaal Orb", 0.12)]).unwrap();
+        insert_opportunities(&mut conn, 6, &[Opportunity::sample("Vaal Orb", 0.11)]).unwrap();
+
+        let rows: Vec<(u64, String, String, f64, f64)> = conn
+            .prepare(
+                "SELECT snapshot_epoch, hub_one, bridge, margin, limiting_volume
+                FROM opportunities ORDER BY snapshot_epoch, bridge",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    5,
+                    "Divine".to_string(),
+                    "Vaal Orb".to_string(),
+                    0.12,
+                    15000.0
+                ),
+                (
+                    6,
+                    "Divine".to_string(),
+                    "Vaal Orb".to_string(),
+                    0.11,
+                    15000.0
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_keeps_snapshots_with_nothing_in_them() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_opportunity_schema(&conn).unwrap();
+        insert_opportunities(&mut conn, 1, &[Opportunity::sample("Vaal Orb", 0.1)]).unwrap();
+        insert_opportunities(&mut conn, 2, &[]).unwrap();
+        insert_opportunities(&mut conn, 3, &[Opportunity::sample("Vaal Orb", 0.1)]).unwrap();
+
+        let history: Vec<(u64, usize)> = get_opportunity_history(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|(epoch, opportunities)| (epoch, opportunities.len()))
+            .collect();
+        assert_eq!(history, vec![(1, 1), (2, 0), (3, 1)]);
+    }
+
+    #[test]
+    fn test_direction_comes_back_from_the_margin() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_opportunity_schema(&conn).unwrap();
+        let found = vec![
+            Opportunity::sample("Regal Orb", -0.2),
+            Opportunity::sample("Vaal Orb", 0.1),
+            Opportunity::sample("Orb of Alchemy", 0.0),
+        ];
+        insert_opportunities(&mut conn, 3, &found).unwrap();
+
+        // Direction isn't stored, it comes back from the margin
+        let directions: Vec<Direction> = get_opportunities(&conn)
+            .unwrap()
+            .iter()
+            .map(|o| o.direction)
+            .collect();
+        assert_eq!(
+            directions,
+            vec![Direction::Reverse, Direction::Forward, Direction::Forward]
+        );
+    }
+
+    #[test]
+    fn test_importing_a_snapshot_again_replaces_its_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let rows = |snapshot_id, count| {
+            (0..count)
+                .map(|pair_id| ExchangeRecord {
+                    pair_id,
+                    snapshot_id,
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+        replace_snapshot_rows(&mut conn, &rows(4, 3)).unwrap();
+        replace_snapshot_rows(&mut conn, &rows(5, 2)).unwrap();
+        // Safe to run on a table that already has rows
+        ensure_schema(&conn).unwrap();
+        replace_snapshot_rows(&mut conn, &rows(4, 1)).unwrap();
+
+        assert_eq!(get_snapshot_ids(&conn).unwrap(), vec![4, 5]);
+        assert_eq!(get_snapshot_entries(&conn, 4).unwrap().len(), 1);
+        assert_eq!(get_snapshot_entries(&conn, 5).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_both_hub_volumes_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_opportunity_schema(&conn).unwrap();
+        insert_opportunities(&mut conn, 2, &[Opportunity::sample("Vaal Orb", 0.1)]).unwrap();
+        let stored = &get_opportunities(&conn).unwrap()[0];
+        assert_eq!(stored.hub_one_volume, 20000.0);
+        assert_eq!(stored.hub_two_volume, 15000.0);
+    }
+
+    #[test]
+    fn test_old_opportunity_tables_get_the_volume_columns() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // What the table looked like before it had the hub volumes
+        conn.execute_batch(
+            "CREATE TABLE opportunities (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            snapshot_epoch INTEGER NOT NULL,
+            recorded_at INTEGER NOT NULL,
+            hub_one TEXT NOT NULL,
+            bridge TEXT NOT NULL,
+            hub_two TEXT NOT NULL,
+            implied_rate REAL NOT NULL,
+            reference_rate REAL NOT NULL,
+            margin REAL NOT NULL,
+            limiting_volume REAL NOT NULL,
+            UNIQUE (snapshot_epoch, hub_one, bridge, hub_two));
+            INSERT INTO opportunities
+            (snapshot_epoch, recorded_at, hub_one, bridge, hub_two, implied_rate, reference_rate, margin, limiting_volume)
+            VALUES (1, 0, 'Divine', 'Regal Orb', 'Exalt', 240.0, 300.0, -0.2, 9000.0);",
+        )
+        .unwrap();
+        ensure_opportunity_schema(&conn).unwrap();
+        insert_opportunities(&mut conn, 2, &[Opportunity::sample("Vaal Orb", 0.1)]).unwrap();
+
+        let volumes: Vec<(f64, f64)> = get_opportunities(&conn)
+            .unwrap()
+            .iter()
+            .map(|o| (o.hub_one_volume, o.hub_two_volume))
+            .collect();
+        assert_eq!(volumes, vec![(9000.0, 9000.0), (20000.0, 15000.0)]);
+    }
+
+    #[test]
+    fn test_has_snapshots() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(!has_snapshots(&conn).unwrap());
+        ensure_schema(&conn).unwrap();
+        assert!(!has_snapshots(&conn).unwrap());
+        insert_all_rows(&[ExchangeRecord::default()], &mut conn).unwrap();
+        assert!(has_snapshots(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_stored_rows_come_back_as_records() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        new_schema(&conn).unwrap();
+        let mut records = Vec::new();
+        for (pair_id, snapshot_id) in [(2, 8), (1, 8), (1, 7)] {
+            let mut record = ExchangeRecord {
+                pair_id,
+                snapshot_id,
+                volume: 500.0,
+                ..Default::default()
+            };
+            record.currency_one.text = "Divine Orb".to_string();
+            record.currency_one_data.relative_price = 300.0;
+            record.currency_two.text = "Vaal Orb".to_string();
+            record.currency_two_data.relative_price = 10.0;
+            records.push(record);
+        }
+        insert_all_rows(&records, &mut conn).unwrap();
+
+        let records: Vec<ExchangeRecord> = get_all_entries(&conn)
+            .unwrap()
+            .into_iter()
+            .map(ExchangeRecord::from)
+            .collect();
+        let ids: Vec<(u64, u64)> = records.iter().map(|r| (r.snapshot_id, r.pair_id)).collect();
+        assert_eq!(ids, vec![(7, 1), (8, 1), (8, 2)]);
+        assert_eq!(records[0].currency_two.text, "Vaal Orb");
+        assert_eq!(records[0].currency_one_data.relative_price, 300.0);
+        assert_eq!(records[0].volume, 500.0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::logic;
+use crate::models::logic_models::{AnalysisParams, Opportunity, RouteKey};
+use crate::source::{MarketSource, SourceResult};
+
+// After this many snapshots in a row a route is probably real and not just
+// one bad listing
+pub const TRUSTED_STREAK: u32 = 3;
+
+// A route that shows up in both snapshots, with the newer numbers
+#[derive(Debug, Clone, Serialize)]
+pub struct PersistedOpportunity {
+    #[serde(flatten)]
+    pub opportunity: Opportunity,
+    pub previous_margin: f64,
+    pub margin_change: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct OpportunityDiff {
+    pub appeared: Vec<Opportunity>,
+    pub disappeared: Vec<Opportunity>,
+    pub persisted: Vec<PersistedOpportunity>,
+}
+
+// Matches the two sets up by route. appeared and disappeared keep the order
+// they came in, persisted is sorted biggest margin move first since that's
+// what you want to look at.
+pub fn diff_opportunities(previous: &[Opportunity], current: &[Opportunity]) -> OpportunityDiff {
+    let previous_by_route: HashMap<RouteKey, &Opportunity> =
+        previous.iter().map(|o| (o.route(), o)).collect();
+    let current_by_route: HashMap<RouteKey, &Opportunity> =
+        current.iter().map(|o| (o.route(), o)).collect();
+
+    let mut diff = OpportunityDiff::default();
+    for opportunity in current {
+        match previous_by_route.get(&opportunity.route()) {
+            Some(old) => diff.persisted.push(PersistedOpportunity {
+                opportunity: opportunity.clone(),
+                previous_margin: old.margin,
+                margin_change: opportunity.margin - old.margin,
+            }),
+            None => diff.appeared.push(opportunity.clone()),
+        }
+    }
+    diff.disappeared = previous
+        .iter()
+        .filter(|o| !current_by_route.contains_key(&o.route()))
+        .cloned()
+        .collect();
+    diff.persisted
+        .sort_by(|a, b| b.margin_change.abs().total_cmp(&a.margin_change.abs()));
+    diff
+}
+
+// Analyzes two of the source's snapshots and diffs what they found
+pub fn diff_snapshots<S: MarketSource + ?Sized>(
+    source: &S,
+    from: u64,
+    to: u64,
+    params: &AnalysisParams,
+) -> SourceResult<OpportunityDiff> {
+    let previous = logic::analyze(source.pairs(from)?, Some(from), params).opportunities;
+    let current = logic::analyze(source.pairs(to)?, Some(to), params).opportunities;
+    Ok(diff_opportunities(&previous, &current))
+}
+
+// How many snapshots in a row each route has been around, fed one diff at a
+// time
+#[derive(Debug, Default)]
+pub struct Streaks {
+    counts: HashMap<RouteKey, u32>,
+}
+
+impl Streaks {
+    pub fn update(&mut self, diff: &OpportunityDiff) {
+        let mut counts = HashMap::new();
+        for opportunity in &diff.appeared {
+            counts.insert(opportunity.route(), 1);
+        }
+        for persisted in &diff.persisted {
+            let route = persisted.opportunity.route();
+            // Something we never saw appear (we started mid streak) counts
+            // from when we first saw it
+            let streak = self.counts.get(&route).copied().unwrap_or(1) + 1;
+            counts.insert(route, streak);
+        }
+        self.counts = counts;
+    }
+
+    pub fn get(&self, route: &RouteKey) -> u32 {
+        self.counts.get(route).copied().unwrap_or(0)
+    }
+
+    pub fn trusted(&self) -> usize {
+        self.counts
+            .values()
+            .filter(|&&streak| streak >= TRUSTED_STREAK)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::logic_models::TradingCurrencyType;
+
+    fn bridges(opportunities: &[Opportunity]) -> Vec<&str> {
+        opportunities.iter().map(|o| o.bridge.as_str()).collect()
+    }
+
+    #[test]
+    fn test_diff_by_route() {
+        let previous = vec![
+            Opportunity::sample("Vaal Orb", 0.10),
+            Opportunity::sample("Regal Orb", 0.08),
+            Opportunity::sample("Orb of Alchemy", 0.2),
+        ];
+        let mut reversed = Opportunity::sample("Vaal Orb", -0.1);
+        reversed.hub_one = TradingCurrencyType::Exalt;
+        reversed.hub_two = TradingCurrencyType::Divine;
+        let current = vec![
+            Opportunity::sample("Vaal Orb", 0.11),
+            Opportunity::sample("Regal Orb", 0.15),
+            reversed,
+        ];
+
+        let diff = diff_opportunities(&previous, &current);
+        // Same bridge the other way round is a different route
+        assert_eq!(bridges(&diff.appeared), vec!["Vaal Orb"]);
+        assert_eq!(diff.appeared[0].hub_one, TradingCurrencyType::Exalt);
+        assert_eq!(bridges(&diff.disappeared), vec!["Orb of Alchemy"]);
+
+        assert_eq!(diff.persisted.len(), 2);
+        assert_eq!(diff.persisted[0].opportunity.bridge, "Regal Orb");
+        assert_eq!(diff.persisted[0].previous_margin, 0.08);
+        assert!((diff.persisted[0].margin_change - 0.07).abs() < 1e-9);
+        assert_eq!(diff.persisted[1].opportunity.bridge, "Vaal Orb");
+    }
+
+    #[test]
+    fn test_streaks() {
+        let mut streaks = Streaks::default();
+        let snapshots = [
+            vec![Opportunity::sample("Vaal Orb", 0.1)],
+            vec![
+                Opportunity::sample("Vaal Orb", 0.1),
+                Opportunity::sample("Regal Orb", 0.1),
+            ],
+            vec![
+                Opportunity::sample("Vaal Orb", 0.1),
+                Opportunity::sample("Regal Orb", 0.1),
+            ],
+            vec![Opportunity::sample("Regal Orb", 0.1)],
+            vec![
+                Opportunity::sample("Vaal Orb", 0.1),
+                Opportunity::sample("Regal Orb", 0.1),
+            ],
+        ];
+        let mut previous = Vec::new();
+        let mut seen = Vec::new();
+        for current in snapshots {
+            streaks.update(&diff_opportunities(&previous, &current));
+            seen.push((
+                streaks.get(&Opportunity::sample("Vaal Orb", 0.0).route()),
+                streaks.get(&Opportunity::sample("Regal Orb", 0.0).route()),
+                streaks.trusted(),
+            ));
+            previous = current;
+        }
+        assert_eq!(
+            seen,
+            vec![(1, 0, 0), (2, 1, 0), (3, 2, 1), (0, 3, 1), (1, 4, 1)]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::io::Write;
+
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::db;
+use crate::models::api_models::{ExchangeQueryResult, ExchangeRecord};
+use crate::output;
+
+// Spelled out so an empty export still gets a header, these have to match the
+// field names below
+const RECORD_COLUMNS: &[&str] = &[
+    "pair_id",
+    "snapshot_id",
+    "volume",
+    "one_id",
+    "one_item_id",
+    "one_currency_category_id",
+    "one_api_id",
+    "one_text",
+    "one_category_api_id",
+    "one_icon_url",
+    "one_highest_stock",
+    "one_relative_price",
+    "one_stock_value",
+    "one_value_traded",
+    "one_volume_traded",
+    "two_id",
+    "two_item_id",
+    "two_currency_category_id",
+    "two_api_id",
+    "two_text",
+    "two_category_api_id",
+    "two_icon_url",
+    "two_highest_stock",
+    "two_relative_price",
+    "two_stock_value",
+    "two_value_traded",
+    "two_volume_traded",
+];
+const QUERY_RESULT_COLUMNS: &[&str] = &[
+    "ts",
+    "pair_id",
+    "snapshot_id",
+    "from_currency",
+    "to_currency",
+    "from_relative_price",
+    "to_relative_price",
+    "volume",
+];
+
+// One row per pair with both sides spelled out, csv can't do nested structs
+#[derive(Debug, Serialize)]
+struct FlatExchangeRecord<'a> {
+    pair_id: u64,
+    snapshot_id: u64,
+    volume: f64,
+    one_id: u64,
+    one_item_id: u64,
+    one_currency_category_id: u64,
+    one_api_id: &'a str,
+    one_text: &'a str,
+    one_category_api_id: &'a str,
+    one_icon_url: &'a str,
+    one_highest_stock: u64,
+    one_relative_price: f64,
+    one_stock_value: f64,
+    one_value_traded: f64,
+    one_volume_traded: u64,
+    two_id: u64,
+    two_item_id: u64,
+    two_currency_category_id: u64,
+    two_api_id: &'a str,
+    two_text: &'a str,
+    two_category_api_id: &'a str,
+    two_icon_url: &'a str,
+    two_highest_stock: u64,
+    two_relative_price: f64,
+    two_stock_value: f64,
+    two_value_traded: f64,
+    two_volume_traded: u64,
+}
+
+impl<'a> FlatExchangeRecord<'a> {
+    fn new(record: &'a ExchangeRecord) -> Self {
+        let (one, one_data) = (&record.currency_one, &record.currency_one_data);
+        let (two, two_data) = (&record.currency_two, &record.currency_two_data);
+        FlatExchangeRecord {
+            pair_id: record.pair_id,
+            snapshot_id: record.snapshot_id,
+            volume: record.volume,
+            one_id: one.id,
+            one_item_id: one.item_id,
+            one_currency_category_id: one.currency_category_id,
+            one_api_id: &one.api_id,
+            one_text: &one.text,
+            one_category_api_id: &one.category_api_id,
+            one_icon_url: &one.icon_url,
+            one_highest_stock: one_data.highest_stock,
+            one_relative_price: one_data.relative_price,
+            one_stock_value: one_data.stock_value,
+            one_value_traded: one_data.value_traded,
+            one_volume_traded: one_data.volume_traded,
+            two_id: two.id,
+            two_item_id: two.item_id,
+            two_currency_category_id: two.currency_category_id,
+            two_api_id: &two.api_id,
+            two_text: &two.text,
+            two_category_api_id: &two.category_api_id,
+            two_icon_url: &two.icon_url,
+            two_highest_stock: two_data.highest_stock,
+            two_relative_price: two_data.relative_price,
+            two_stock_value: two_data.stock_value,
+            two_value_traded: two_data.value_traded,
+            two_volume_traded: two_data.volume_traded,
+        }
+    }
+}
+
+pub fn write_records_csv(out: impl Write, records: &[ExchangeRecord]) -> csv::Result<()> {
+    output::write_csv(
+        out,
+        RECORD_COLUMNS,
+        records.iter().map(FlatExchangeRecord::new),
+    )
+}
+
+// Everything the database stored between two unix times, gives back how many
+// rows went out. exchange_rates only fills up as snapshots get fetched (with
+// record_snapshots on), so a database with nothing in it at all is an error
+// rather than an empty file that looks like a quiet day.
+pub fn export_range(
+    conn: &Connection,
+    from_ts: u64,
+    to_ts: u64,
+    out: impl Write,
+) -> Result<usize, Box<dyn Error>> {
+    if !db::has_snapshots(conn)? {
+        return Err(
+            "the database has no snapshots in it, they're saved as they're fetched \
+            unless record_snapshots is off"
+                .into(),
+        );
+    }
+    let rows = db::get_entries_between(conn, from_ts, to_ts)?;
+    write_query_results_csv(out, &rows)?;
+    Ok(rows.len())
+}
+
+// The database only keeps the parts of a record we use, so this has fewer
+// columns than the snapshot export
+pub fn write_query_results_csv(out: impl Write, rows: &[ExchangeQueryResult]) -> csv::Result<()> {
+    output::write_csv(out, QUERY_RESULT_COLUMNS, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flattened_columns() {
+        let mut record = ExchangeRecord {
+            pair_id: 3,
+            volume: 1500.0,
+            ..Default::default()
+        };
+        record.currency_one.text = "Exalted Orb".to_string();
+        record.currency_two.text = "Vaal Orb, the \"good\" one".to_string();
+        record.currency_two_data.relative_price = 2.5;
+
+        let mut out = Vec::new();
+        write_records_csv(&mut out, &[record]).unwrap();
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(headers.len(), 27);
+        assert_eq!(&headers[0], "pair_id");
+        assert_eq!(&headers[7], "one_text");
+
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[0], "3");
+        assert_eq!(&row[7], "Exalted Orb");
+        let two_text = headers.iter().position(|h| h == "two_text").unwrap();
+        assert_eq!(&row[two_text], "Vaal Orb, the \"good\" one");
+        let two_price = headers
+            .iter()
+            .position(|h| h == "two_relative_price")
+            .unwrap();
+        assert_eq!(&row[two_price], "2.5");
+    }
+
+    #[test]
+    fn test_empty_exports_have_headers() {
+        let mut out = Vec::new();
+        write_records_csv(&mut out, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}\n", RECORD_COLUMNS.join(","))
+        );
+
+        let mut out = Vec::new();
+        write_query_results_csv(&mut out, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}\n", QUERY_RESULT_COLUMNS.join(","))
+        );
+    }
+
+    #[test]
+    fn test_export_range_needs_snapshots() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(export_range(&conn, 0, i64::MAX as u64, Vec::new()).is_err());
+
+        db::ensure_schema(&conn).unwrap();
+        let record = ExchangeRecord {
+            snapshot_id: 4,
+            ..Default::default()
+        };
+        db::replace_snapshot_rows(&mut conn, &[record]).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(
+            export_range(&conn, 0, i64::MAX as u64, &mut out).unwrap(),
+            1
+        );
+        // Nothing in the range is fine though, that's just a header
+        assert_eq!(export_range(&conn, 0, 1, Vec::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_columns_match_the_rows() {
+        let record = ExchangeRecord::default();
+        assert_eq!(
+            output::serde_columns(FlatExchangeRecord::new(&record)),
+            RECORD_COLUMNS.join(",")
+        );
+        let row = ExchangeQueryResult {
+            ts: 1,
+            pair_id: 2,
+            snapshot_id: 3,
+            from_currency: "Divine Orb".to_string(),
+            to_currency: "Vaal Orb".to_string(),
+            from_relative_price: 300.0,
+            to_relative_price: 10.0,
+            volume: 500.0,
+        };
+        assert_eq!(output::serde_columns(row), QUERY_RESULT_COLUMNS.join(","));
+    }
+}
@@ -0,0 +1,48 @@
+//! Digs up triple arbitrage flips on the POE2 currency exchange.
+//!
+//! The finder is split so other tools can use the pieces without the CLI:
+//!
+//! - [`api`] fetches snapshots from poe2scout
+//! - [`logic`] runs the opportunity search over a snapshot's records
+//! - [`db`] stores raw pairs and found opportunities in SQLite
+//! - [`models`] has the types all of those pass around
+//! - [`source`] is where snapshots come from, implement
+//!   [`source::MarketSource`] to add a new one
+//! - [`synth`] makes up markets from a seed, for tests, benchmarks and demos
+//!
+//! The rest (cache, output, watch, backtest and friends) is what the
+//! `poe_flip_finder` binary is built from. It's public so the binary can get
+//! at it, but it's shaped around the CLI more than the ones above are.
+//!
+//! ```no_run
+//! use poe_flip_finder::models::logic_models::AnalysisParams;
+//! use poe_flip_finder::{api, logic};
+//!
+//! let client = api::build_client()?;
+//! let epoch = api::get_exchange_snapshot(&client)?.epoch;
+//! let records = api::get_newest_snapshot_pairs(&client)?;
+//! let analysis = logic::analyze(records, Some(epoch), &AnalysisParams::default());
+//! for opportunity in logic::rank_by_margin(&analysis.opportunities, 5) {
+//!     println!("{} via {}: {:+.1}%", opportunity.hub_one, opportunity.bridge, opportunity.margin * 100.0);
+//! }
+//! # Ok::<(), reqwest::Error>(())
+//! ```
+
+pub mod api;
+pub mod db;
+pub mod logic;
+pub mod models;
+pub mod source;
+pub mod synth;
+
+pub mod alert;
+pub mod backtest;
+pub mod cache;
+pub mod config;
+pub mod diff;
+pub mod export;
+pub mod output;
+pub mod run;
+pub mod stats;
+pub mod validation;
+pub mod watch;
@@ -1,13 +1,89 @@
-use std::collections::HashMap;
-use std::fs::{self, DirEntry, File};
-use std::io::BufWriter;
-use std::path::Path;
+//! The opportunity search. [`analyze`] runs the whole thing on one
+//! snapshot, the pieces it's built from are public too if you want to poke
+//! at the intermediate maps.
 
-use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
 
 use crate::models::api_models::ExchangeRecord;
-use crate::models::logic_models::{TradingCurrencyRates, TradingCurrencyType};
+use crate::models::logic_models::{
+    AnalysisParams, BridgeId, BridgeRoute, Direction, Opportunity, RankStrategy,
+    TradingCurrencyRates, TradingCurrencyType, ValidationReport,
+};
+use crate::validation;
+
+/// Everything [`analyze`] found in one snapshot
+pub struct Analysis {
+    pub validation_report: ValidationReport,
+    pub base_rates: TradingCurrencyRates,
+    /// Sorted by the bridged price, highest first
+    pub opportunities: Vec<Opportunity>,
+}
+
+/// The whole thing end to end: clean up the records, work out the base rates,
+/// then find every bridge that's far enough off them.
+pub fn analyze(
+    records: Vec<ExchangeRecord>,
+    snapshot_epoch: Option<u64>,
+    params: &AnalysisParams,
+) -> Analysis {
+    let (records, validation_report) = validation::validate_records(records);
+
+    // These are the base rates we need to compare against.
+    let mut base_rates = TradingCurrencyRates::default();
+    get_base_prices(&records, &mut base_rates);
+
+    let valid_bridges: Vec<ExchangeRecord> = records
+        .into_iter()
+        .filter(|exch| exch.volume >= params.min_vol && exch.is_valid_bridge())
+        .collect();
+
+    let bridge_maps = build_hub_bridge_maps(&valid_bridges);
 
+    let mut potential_profits = build_bridges(&bridge_maps);
+    potential_profits.retain(|route| eval_profit(route, &base_rates, params.min_profit_frac));
+    potential_profits.sort_by(|a, b| b.implied_rate.total_cmp(&a.implied_rate));
+
+    let opportunities = potential_profits
+        .into_iter()
+        .filter_map(|route| to_opportunity(route, &base_rates, &bridge_maps, snapshot_epoch))
+        .collect();
+
+    Analysis {
+        validation_report,
+        base_rates,
+        opportunities,
+    }
+}
+
+fn to_opportunity(
+    route: BridgeRoute,
+    base_rates: &TradingCurrencyRates,
+    bridge_maps: &BridgeMaps,
+    snapshot_epoch: Option<u64>,
+) -> Option<Opportunity> {
+    // A snapshot missing one of the base pairs leaves its rate at zero (or
+    // worse), and there's no margin to be had against that
+    let reference_rate = base_rates
+        .reference_rate(route.hub_one, route.hub_two)
+        .filter(|rate| rate.is_finite() && *rate > 0.0)?;
+    let margin = (route.implied_rate - reference_rate) / reference_rate;
+    Some(Opportunity {
+        hub_one: route.hub_one,
+        bridge: bridge_maps.name(route.bridge).to_string(),
+        hub_two: route.hub_two,
+        implied_rate: route.implied_rate,
+        reference_rate,
+        margin,
+        direction: Direction::from_margin(margin),
+        hub_one_volume: bridge_maps.volume(route.hub_one, route.bridge),
+        hub_two_volume: bridge_maps.volume(route.hub_two, route.bridge),
+        snapshot_epoch,
+    })
+}
+
+/// Fills in the divine and chaos prices in exalts, and the divine to chaos
+/// rate from those
 pub fn get_base_prices(records: &[ExchangeRecord], rates: &mut TradingCurrencyRates) {
     for record in records {
         let currency_pair = record.trading_currency();
@@ -24,110 +100,152 @@ pub fn get_base_prices(records: &[ExchangeRecord], rates: &mut TradingCurrencyRa
     rates.div_to_chaos = rates.div_to_exalt / rates.chaos_to_exalt
 }
 
-pub fn list_all_snapshots(path: &Path) -> Vec<DirEntry> {
-    let paths = fs::read_dir(path).unwrap();
-    let mut out_vec: Vec<DirEntry> = vec![];
-    for file_name in paths.filter(|f| {
-        f.as_ref().unwrap().path().is_file()
-            && f.as_ref().unwrap().path().extension().unwrap() == "json"
-    }) {
-        out_vec.push(file_name.unwrap());
+// What do we have to do once we have the values?
+// I think we're going to iterate over the filtered vector one time
+// Maybe we can do it after the filter step.
+// for each record we get the trading currency rate and we compare
+// the absolute difference of that rate to the matching tradingcurrencyrate value.
+// I think we then have another struct that's like {diff, ExchangeRecord} and
+// push that into a new vec. Sort that vec by absolute difference, then we can
+// pretty print the output? We need to compute the expected return at some point.
+
+// The hubs in the order BridgeMaps lays its slots out in
+pub(crate) const HUBS: [TradingCurrencyType; 3] = [
+    TradingCurrencyType::Exalt,
+    TradingCurrencyType::Chaos,
+    TradingCurrencyType::Divine,
+];
+
+fn hub_slot(hub: TradingCurrencyType) -> Option<usize> {
+    match hub {
+        TradingCurrencyType::Exalt => Some(0),
+        TradingCurrencyType::Chaos => Some(1),
+        TradingCurrencyType::Divine => Some(2),
+        TradingCurrencyType::Other => None,
     }
-    out_vec
 }
 
-fn get_snapshot_number_from_name(snapshot_name: &str) -> Option<u64> {
-    let underscore_idx = snapshot_name.find("_")?;
-    let dot_idx = snapshot_name.find(".")?;
-    snapshot_name[underscore_idx + 1..dot_idx]
-        .parse::<u64>()
-        .ok()
+/// Both directions of every hub <-> bridge rate and the volume behind it.
+/// Each bridge gets a number and a row with a slot per hub, so the route
+/// search is just indexing, no hashing or cloning names.
+#[derive(Debug, Default)]
+pub struct BridgeMaps {
+    names: Vec<String>,
+    ids: HashMap<String, BridgeId>,
+    // How many of the bridge one hub gets you
+    hub_to_bridge: Vec<[Option<f64>; 3]>,
+    // How many of the hub one bridge gets you
+    bridge_to_hub: Vec<[Option<f64>; 3]>,
+    volumes: Vec<[f64; 3]>,
 }
 
-pub fn check_if_snapshot_exists(newest_snapshot: u64, snapshot_list: &[DirEntry]) -> bool {
-    for snapshot in snapshot_list {
-        if newest_snapshot
-            == get_snapshot_number_from_name(snapshot.file_name().to_str().unwrap()).unwrap()
-        {
-            return true;
-        }
+impl BridgeMaps {
+    /// How many bridge currencies there are
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Every bridge's id, in the order they first showed up
+    pub fn ids(&self) -> impl Iterator<Item = BridgeId> + use<> {
+        (0..self.names.len()).map(BridgeId)
+    }
+
+    pub fn id(&self, name: &str) -> Option<BridgeId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, bridge: BridgeId) -> &str {
+        &self.names[bridge.0]
+    }
+
+    /// How many of the bridge one `hub` gets you, if they trade
+    pub fn hub_to_bridge(&self, hub: TradingCurrencyType, bridge: BridgeId) -> Option<f64> {
+        self.hub_to_bridge[bridge.0][hub_slot(hub)?]
+    }
+
+    /// How many `hub` one of the bridge gets you, if they trade
+    pub fn bridge_to_hub(&self, bridge: BridgeId, hub: TradingCurrencyType) -> Option<f64> {
+        self.bridge_to_hub[bridge.0][hub_slot(hub)?]
     }
-    false
-}
 
-pub fn cache_to_disk(
-    data: &impl Serialize,
-    path_dir: &Path,
-    filename: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_path = path_dir.join(filename);
-    let file = File::create(file_path)?;
-    let writer = BufWriter::new(file);
+    /// Volume on the hub <-> bridge pair, 0 if there isn't one
+    pub fn volume(&self, hub: TradingCurrencyType, bridge: BridgeId) -> f64 {
+        hub_slot(hub).map_or(0.0, |slot| self.volumes[bridge.0][slot])
+    }
 
-    serde_json::to_writer(writer, data)?;
-    Ok(())
+    fn intern(&mut self, name: &str) -> BridgeId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = BridgeId(self.names.len());
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        self.hub_to_bridge.push([None; 3]);
+        self.bridge_to_hub.push([None; 3]);
+        self.volumes.push([0.0; 3]);
+        id
+    }
 }
-// What do we have to do once we have the values?
-// I think we're going to iterate over the filtered vector one time
-// Maybe we can do it after the filter step.
-// for each record we get the trading currency rate and we compare
-// the absolute difference of that rate to the matching tradingcurrencyrate value.
-// I think we then have another struct that's like {diff, ExchangeRecord} and
-// push that into a new vec. Sort that vec by absolute difference, then we can
-// pretty print the output? We need to compute the expected return at some point.
 
-pub fn build_hub_bridge_maps(
-    records: &[ExchangeRecord],
-) -> (
-    HashMap<(TradingCurrencyType, String), f64>,
-    HashMap<(String, TradingCurrencyType), f64>,
-) {
+/// Every hub <-> bridge pair in `records`, a later listing of the same pair
+/// wins
+pub fn build_hub_bridge_maps(records: &[ExchangeRecord]) -> BridgeMaps {
     // Build our lookup tables here so it's faster to scan every single
     // combination instead of looping through the vec of records a bazillion times
-    let mut hub_to_bridge = HashMap::new();
-    let mut bridge_to_hub = HashMap::new();
+    let mut maps = BridgeMaps::default();
 
     for record in records {
         if let Some((hub, hub_ex, bridge_str, bridge_ex)) = record.hub_bridge_price() {
+            // hub_bridge_price only hands back hubs on the hub side
+            let Some(slot) = hub_slot(hub) else {
+                continue;
+            };
+            let bridge = maps.intern(bridge_str);
             let hub_per_bridge_ratio = hub_ex / bridge_ex;
-            hub_to_bridge.insert((hub.clone(), bridge_str.clone()), hub_per_bridge_ratio);
-            bridge_to_hub.insert((bridge_str, hub), hub_per_bridge_ratio.recip());
+            maps.hub_to_bridge[bridge.0][slot] = Some(hub_per_bridge_ratio);
+            maps.bridge_to_hub[bridge.0][slot] = Some(hub_per_bridge_ratio.recip());
+            maps.volumes[bridge.0][slot] = record.volume;
         }
     }
-    (hub_to_bridge, bridge_to_hub)
+    maps
 }
 
-pub fn build_bridges(
-    hub_to_bridge: &HashMap<(TradingCurrencyType, String), f64>,
-    bridge_to_hub: &HashMap<(String, TradingCurrencyType), f64>,
-) -> Vec<(TradingCurrencyType, String, TradingCurrencyType, f64)> {
+/// Every hub -> bridge -> hub route we have both legs for, with the implied
+/// rate (second hub per first hub) going through the bridge
+pub fn build_bridges(maps: &BridgeMaps) -> Vec<BridgeRoute> {
     let mut results = Vec::new();
 
-    let hubs = [
-        TradingCurrencyType::Exalt,
-        TradingCurrencyType::Chaos,
-        TradingCurrencyType::Divine,
-    ];
-
-    for &first_hub in &hubs {
-        for &second_hub in &hubs {
-            if first_hub == second_hub {
+    for (idx, (to_bridge, to_hub)) in maps
+        .hub_to_bridge
+        .iter()
+        .zip(&maps.bridge_to_hub)
+        .enumerate()
+    {
+        for (first, rate_one) in to_bridge.iter().enumerate() {
+            let Some(rate_one) = rate_one else {
                 continue;
-            }
-            // Now we grind through everything
-
-            for ((_hub_one, bridge), rate_one) in
-                hub_to_bridge.iter().filter(|((h, _), _)| *h == first_hub)
-            {
-                if let Some(rate_two) = bridge_to_hub.get(&(bridge.clone(), second_hub)) {
+            };
+            for (second, rate_two) in to_hub.iter().enumerate() {
+                if first == second {
+                    continue;
+                }
+                if let Some(rate_two) = rate_two {
                     // If we have a rate two, this means we went A -> X -> B
                     // rate one is norm(A)/norm(X) and rate two is norm(X)/norm(B)
                     // so multiplying gives us norm(A)/norm(B)
                     // when B is exalts, norm(B) should be close to 1, so this
                     // will give us the relative price for A through the bridge
-                    // I *think* this is right...
-                    let cost = rate_one * rate_two;
-                    results.push((first_hub, bridge.clone(), second_hub, cost));
+                    // The property tests in this file check this holds up
+                    results.push(BridgeRoute {
+                        hub_one: HUBS[first],
+                        bridge: BridgeId(idx),
+                        hub_two: HUBS[second],
+                        implied_rate: rate_one * rate_two,
+                    });
                 }
             }
         }
@@ -135,27 +253,435 @@ pub fn build_bridges(
     results
 }
 
+/// Whether a route from [`build_bridges`] is far enough off the base rate,
+/// either direction, to count as an opportunity
 pub fn eval_profit(
-    bridge_elem: &(TradingCurrencyType, String, TradingCurrencyType, f64),
+    route: &BridgeRoute,
     ratios: &TradingCurrencyRates,
     min_profit_frac: f64,
 ) -> bool {
-    let tc1 = &bridge_elem.0;
-    let tc2 = &bridge_elem.2;
     let div_frac = min_profit_frac * ratios.div_to_exalt;
     let chaos_frac = min_profit_frac * ratios.chaos_to_exalt;
     let div_chaos_frac = min_profit_frac * ratios.div_to_chaos;
-    match (tc1, tc2) {
+    match (route.hub_one, route.hub_two) {
         (TradingCurrencyType::Divine, TradingCurrencyType::Exalt) => {
-            (bridge_elem.3 - ratios.div_to_exalt).abs() >= div_frac
+            (route.implied_rate - ratios.div_to_exalt).abs() >= div_frac
         }
         (TradingCurrencyType::Chaos, TradingCurrencyType::Exalt) => {
-            (bridge_elem.3 - ratios.chaos_to_exalt).abs() >= chaos_frac
+            (route.implied_rate - ratios.chaos_to_exalt).abs() >= chaos_frac
         }
         (TradingCurrencyType::Divine, TradingCurrencyType::Chaos) => {
-            (bridge_elem.3 - ratios.div_to_chaos).abs() >= div_chaos_frac
+            (route.implied_rate - ratios.div_to_chaos).abs() >= div_chaos_frac
         }
         // (TradingCurrencyType::Exalt, TradingCurrencyType::Divine) => true,
         (_, _) => false,
     }
 }
+
+/// Rough size of the prize: how far off the base rate it is, times the volume
+/// of the thinner leg since that's all we could realistically push through
+pub fn expected_profit(opportunity: &Opportunity) -> f64 {
+    opportunity.margin.abs() * opportunity.hub_one_volume.min(opportunity.hub_two_volume)
+}
+
+/// Cuts the list down to the best `top_n` by `strategy`, per group for the
+/// grouped strategies
+pub fn rank_opportunities(
+    opportunities: &[Opportunity],
+    strategy: RankStrategy,
+    top_n: usize,
+) -> Vec<&Opportunity> {
+    match strategy {
+        RankStrategy::Margin => rank_by_margin(opportunities, top_n),
+        RankStrategy::HubPair => rank_per_hub_pair(opportunities, top_n),
+        RankStrategy::Bridge => rank_per_bridge(opportunities, top_n),
+        RankStrategy::Profit => rank_by_expected_profit(opportunities, top_n),
+    }
+}
+
+// A big negative margin is just as good as a big positive one, you run the
+// route the other way round
+fn by_margin_desc(opportunities: &[Opportunity]) -> Vec<&Opportunity> {
+    let mut sorted: Vec<&Opportunity> = opportunities.iter().collect();
+    sorted.sort_by(|a, b| b.margin.abs().total_cmp(&a.margin.abs()));
+    sorted
+}
+
+/// Biggest margins first, either sign
+pub fn rank_by_margin(opportunities: &[Opportunity], top_n: usize) -> Vec<&Opportunity> {
+    let mut ranked = by_margin_desc(opportunities);
+    ranked.truncate(top_n);
+    ranked
+}
+
+/// Biggest [`expected_profit`] first
+pub fn rank_by_expected_profit(opportunities: &[Opportunity], top_n: usize) -> Vec<&Opportunity> {
+    let mut ranked: Vec<&Opportunity> = opportunities.iter().collect();
+    ranked.sort_by(|a, b| expected_profit(b).total_cmp(&expected_profit(a)));
+    ranked.truncate(top_n);
+    ranked
+}
+
+/// The best `top_n` for each pair of hubs
+pub fn rank_per_hub_pair(opportunities: &[Opportunity], top_n: usize) -> Vec<&Opportunity> {
+    rank_grouped(opportunities, top_n, |o| (o.hub_one, o.hub_two))
+}
+
+/// The best `top_n` for each bridge currency
+pub fn rank_per_bridge(opportunities: &[Opportunity], top_n: usize) -> Vec<&Opportunity> {
+    rank_grouped(opportunities, top_n, |o| o.bridge.as_str())
+}
+
+// Top N by margin within each group. Groups come out together, the group
+// with the best single opportunity first.
+fn rank_grouped<'a, K: Eq + Hash>(
+    opportunities: &'a [Opportunity],
+    top_n: usize,
+    key: impl Fn(&'a Opportunity) -> K,
+) -> Vec<&'a Opportunity> {
+    let mut group_order: HashMap<K, (usize, usize)> = HashMap::new();
+    let mut ranked: Vec<(usize, &Opportunity)> = Vec::new();
+
+    for opportunity in by_margin_desc(opportunities) {
+        let next_group = group_order.len();
+        let (group, count) = group_order
+            .entry(key(opportunity))
+            .or_insert((next_group, 0));
+        if *count < top_n {
+            *count += 1;
+            ranked.push((*group, opportunity));
+        }
+    }
+    // Stable, so each group stays in margin order
+    ranked.sort_by_key(|(group, _)| *group);
+    ranked
+        .into_iter()
+        .map(|(_, opportunity)| opportunity)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::logic_models::RouteKey;
+    use crate::synth::{self, SynthConfig, SynthMarket};
+    use proptest::prelude::*;
+
+    fn fixture_analysis() -> Analysis {
+        let records: Vec<ExchangeRecord> = serde_json::from_str(include_str!(
+            "../tests/fixtures/replay/synthetic_small/pairs.json"
+        ))
+        .unwrap();
+        analyze(records, Some(77), &AnalysisParams::default())
+    }
+
+    fn routes(ranked: &[&Opportunity]) -> Vec<RouteKey> {
+        ranked.iter().map(|o| o.route()).collect()
+    }
+
+    fn route(hub_one: TradingCurrencyType, bridge: &str, hub_two: TradingCurrencyType) -> RouteKey {
+        RouteKey {
+            hub_one,
+            bridge: bridge.to_string(),
+            hub_two,
+        }
+    }
+
+    #[test]
+    fn test_fixture_opportunities() {
+        let analysis = fixture_analysis();
+        // Gemcutter's is priced consistently and Alchemy is under min_vol
+        assert_eq!(analysis.opportunities.len(), 6);
+        assert!(
+            analysis
+                .opportunities
+                .iter()
+                .all(|o| o.bridge != "Gemcutter's Prism" && o.bridge != "Orb of Alchemy")
+        );
+        let vaal = analysis
+            .opportunities
+            .iter()
+            .find(|o| o.bridge == "Vaal Orb" && o.hub_one == TradingCurrencyType::Divine)
+            .unwrap();
+        assert!((vaal.implied_rate - 330.0).abs() < 1e-9);
+        assert!((vaal.margin - 0.1).abs() < 1e-9);
+        assert_eq!(vaal.direction, Direction::Forward);
+        assert!(
+            analysis
+                .opportunities
+                .iter()
+                .all(|o| o.direction == Direction::from_margin(o.margin))
+        );
+        assert_eq!(vaal.hub_one_volume, 40000.0);
+        assert_eq!(vaal.hub_two_volume, 60000.0);
+    }
+
+    #[test]
+    fn test_no_opportunities_without_a_base_rate() {
+        let mut records: Vec<ExchangeRecord> = serde_json::from_str(include_str!(
+            "../tests/fixtures/replay/synthetic_small/pairs.json"
+        ))
+        .unwrap();
+        // Without the chaos -> exalt pair there's no chaos price to compare to
+        records.retain(|r| {
+            r.trading_currency() != (TradingCurrencyType::Chaos, TradingCurrencyType::Exalt)
+        });
+        let analysis = analyze(records, Some(77), &AnalysisParams::default());
+        assert!(!analysis.opportunities.is_empty());
+        assert!(analysis.opportunities.iter().all(|o| {
+            o.margin.is_finite()
+                && o.hub_one != TradingCurrencyType::Chaos
+                && o.hub_two != TradingCurrencyType::Chaos
+        }));
+    }
+
+    #[test]
+    fn test_bridge_maps() {
+        let records: Vec<ExchangeRecord> = serde_json::from_str(include_str!(
+            "../tests/fixtures/replay/synthetic_small/pairs.json"
+        ))
+        .unwrap();
+        let maps = build_hub_bridge_maps(&records);
+        // Listed against the divine and the exalt, one id for both
+        let vaal = maps.id("Vaal Orb").unwrap();
+        assert_eq!(maps.name(vaal), "Vaal Orb");
+        assert!(
+            maps.hub_to_bridge(TradingCurrencyType::Divine, vaal)
+                .is_some()
+        );
+        assert!(
+            maps.hub_to_bridge(TradingCurrencyType::Exalt, vaal)
+                .is_some()
+        );
+        assert_eq!(maps.volume(TradingCurrencyType::Divine, vaal), 40000.0);
+        assert_eq!(maps.ids().count(), maps.len());
+        assert_eq!(maps.id("Divine Orb"), None);
+
+        let routes = build_bridges(&maps);
+        assert!(routes.iter().all(|route| route.hub_one != route.hub_two));
+        assert!(
+            routes
+                .iter()
+                .any(|route| route.bridge == vaal && route.hub_one == TradingCurrencyType::Divine)
+        );
+    }
+
+    #[test]
+    fn test_rank_by_margin() {
+        let analysis = fixture_analysis();
+        let ranked = rank_by_margin(&analysis.opportunities, 2);
+        let mut top = routes(&ranked);
+        top.sort_by_key(|r| r.hub_one as u8);
+        // Both annulment routes are 20% under
+        assert_eq!(
+            top,
+            vec![
+                route(
+                    TradingCurrencyType::Chaos,
+                    "Orb of Annulment",
+                    TradingCurrencyType::Exalt
+                ),
+                route(
+                    TradingCurrencyType::Divine,
+                    "Orb of Annulment",
+                    TradingCurrencyType::Exalt
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rank_per_hub_pair() {
+        let analysis = fixture_analysis();
+        let ranked = rank_per_hub_pair(&analysis.opportunities, 1);
+        assert_eq!(ranked.len(), 3);
+        let pairs: Vec<(TradingCurrencyType, TradingCurrencyType)> =
+            ranked.iter().map(|o| (o.hub_one, o.hub_two)).collect();
+        assert!(pairs.contains(&(TradingCurrencyType::Divine, TradingCurrencyType::Exalt)));
+        assert!(pairs.contains(&(TradingCurrencyType::Chaos, TradingCurrencyType::Exalt)));
+        assert!(pairs.contains(&(TradingCurrencyType::Divine, TradingCurrencyType::Chaos)));
+        // The best of each pair, the 20% ones first
+        assert_eq!(ranked[0].bridge, "Orb of Annulment");
+        assert_eq!(ranked[1].bridge, "Orb of Annulment");
+        assert_eq!(ranked[2].bridge, "Regal Orb");
+    }
+
+    #[test]
+    fn test_rank_per_bridge() {
+        let analysis = fixture_analysis();
+        let ranked = rank_per_bridge(&analysis.opportunities, 1);
+        let bridges: Vec<&str> = ranked.iter().map(|o| o.bridge.as_str()).collect();
+        assert_eq!(bridges[0], "Orb of Annulment");
+        assert_eq!(bridges.len(), 3);
+        assert!(bridges.contains(&"Vaal Orb"));
+        assert!(bridges.contains(&"Regal Orb"));
+
+        // Groups stay together when there's more than one per bridge
+        let ranked = rank_per_bridge(&analysis.opportunities, 5);
+        assert_eq!(ranked.len(), 6);
+        let bridges: Vec<&str> = ranked.iter().map(|o| o.bridge.as_str()).collect();
+        for bridge in ["Orb of Annulment", "Vaal Orb", "Regal Orb"] {
+            let first = bridges.iter().position(|b| *b == bridge).unwrap();
+            let last = bridges.iter().rposition(|b| *b == bridge).unwrap();
+            assert_eq!(last - first, 1, "{} isn't grouped", bridge);
+        }
+    }
+
+    #[test]
+    fn test_rank_by_expected_profit() {
+        let analysis = fixture_analysis();
+        let ranked = rank_by_expected_profit(&analysis.opportunities, 1);
+        // 10% of 40000 on the divine vaal route beats 20% of 12000 on annulment
+        assert_eq!(
+            routes(&ranked),
+            vec![route(
+                TradingCurrencyType::Divine,
+                "Vaal Orb",
+                TradingCurrencyType::Exalt
+            )]
+        );
+        assert!((expected_profit(ranked[0]) - 4000.0).abs() < 1e-6);
+    }
+
+    // The routes eval_profit knows the base rate for
+    const PRICED_ROUTES: [(TradingCurrencyType, TradingCurrencyType); 3] = [
+        (TradingCurrencyType::Divine, TradingCurrencyType::Exalt),
+        (TradingCurrencyType::Chaos, TradingCurrencyType::Exalt),
+        (TradingCurrencyType::Divine, TradingCurrencyType::Chaos),
+    ];
+
+    // Divine and chaos prices in exalts over a much wider range than synth's
+    // defaults, so the bridge math gets tried at other price levels
+    fn hub_prices() -> impl Strategy<Value = (f64, f64)> {
+        (50.0..1000.0f64, 2.0..50.0f64)
+    }
+
+    // A synth market with every bridge listed against every hub at its real
+    // value and with plenty of volume, apart from `planted_loops` of them
+    // mispriced against one hub
+    fn clean_market(planted_loops: usize) -> impl Strategy<Value = SynthMarket> {
+        (any::<u64>(), 1..8usize, hub_prices(), mispricing()).prop_map(
+            move |(seed, currencies, (div_to_exalt, chaos_to_exalt), planted_margin)| {
+                let config = SynthConfig {
+                    currencies,
+                    div_to_exalt,
+                    chaos_to_exalt,
+                    min_liquidity: 1.0,
+                    noise: 0.0,
+                    coverage: 1.0,
+                    planted_loops,
+                    planted_margin,
+                    ..Default::default()
+                };
+                synth::generate(seed, 1, &config)
+            },
+        )
+    }
+
+    // Off by at least 10% either way, comfortably past the default 5%
+    fn mispricing() -> impl Strategy<Value = f64> {
+        0.1..0.5f64
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+    }
+
+    fn priced_routes(market: &SynthMarket) -> Vec<BridgeRoute> {
+        let mut base_rates = TradingCurrencyRates::default();
+        get_base_prices(&market.records, &mut base_rates);
+        let mut routes = build_bridges(&build_hub_bridge_maps(&market.records));
+        routes.retain(|route| eval_profit(route, &base_rates, 0.01));
+        routes
+    }
+
+    proptest! {
+        #[test]
+        fn test_consistent_market_has_no_opportunities(market in clean_market(0)) {
+            prop_assert!(priced_routes(&market).is_empty());
+            let analysis = analyze(market.records, None, &AnalysisParams::default());
+            prop_assert!(analysis.opportunities.is_empty());
+        }
+
+        #[test]
+        fn test_bridges_match_base_rates(market in clean_market(0)) {
+            let routes = build_bridges(&build_hub_bridge_maps(&market.records));
+            // Two hub pairs, then every bridge against all three hubs
+            let bridges = (market.records.len() - 2) / 3;
+            // Every ordered pair of hubs through every bridge
+            prop_assert_eq!(routes.len(), 6 * bridges);
+            for route in routes {
+                let expected = market.hub_price(route.hub_one).unwrap()
+                    / market.hub_price(route.hub_two).unwrap();
+                prop_assert!(
+                    close(route.implied_rate, expected),
+                    "{} vs {}",
+                    route.implied_rate,
+                    expected
+                );
+            }
+        }
+
+        #[test]
+        fn test_single_mispricing_is_found(market in clean_market(1)) {
+            // The bridge looks `factor` times as valuable in its pair with
+            // one hub as it does everywhere else
+            let plant = market.planted[0].clone();
+
+            let analysis = analyze(market.records, None, &AnalysisParams::default());
+            // The two priced routes that touch that hub, nothing else
+            prop_assert_eq!(analysis.opportunities.len(), 2);
+            for opportunity in &analysis.opportunities {
+                prop_assert_eq!(&opportunity.bridge, &plant.bridge);
+                // Buying the bridge with the mispriced hub gets you 1/factor
+                // as many, selling it for that hub gets you factor as much
+                let expected_margin = if opportunity.hub_one == plant.hub {
+                    1.0 / plant.factor - 1.0
+                } else {
+                    prop_assert_eq!(opportunity.hub_two, plant.hub);
+                    plant.factor - 1.0
+                };
+                prop_assert!(
+                    PRICED_ROUTES.contains(&(opportunity.hub_one, opportunity.hub_two))
+                );
+                prop_assert_eq!(opportunity.margin.signum(), expected_margin.signum());
+                prop_assert_eq!(opportunity.direction, Direction::from_margin(expected_margin));
+                prop_assert!(
+                    close(opportunity.margin, expected_margin),
+                    "{} vs {}",
+                    opportunity.margin,
+                    expected_margin
+                );
+            }
+        }
+
+        #[test]
+        fn test_hub_bridge_rates_are_reciprocal(
+            seed in any::<u64>(),
+            currencies in 1..20usize,
+            (div_to_exalt, chaos_to_exalt) in hub_prices(),
+        ) {
+            // Noisy and patchy, the rates only have to undo each other
+            let config = SynthConfig {
+                currencies,
+                div_to_exalt,
+                chaos_to_exalt,
+                noise: 0.5,
+                coverage: 0.5,
+                ..Default::default()
+            };
+            let market = synth::generate(seed, 1, &config);
+            let maps = build_hub_bridge_maps(&market.records);
+            prop_assert!(maps.len() <= currencies);
+            for bridge in maps.ids() {
+                for hub in HUBS {
+                    let Some(rate) = maps.hub_to_bridge(hub, bridge) else {
+                        continue;
+                    };
+                    let back = maps.bridge_to_hub(bridge, hub).unwrap();
+                    prop_assert!(close(rate * back, 1.0), "{} * {} != 1", rate, back);
+                }
+            }
+        }
+    }
+}
@@ -1,111 +1,332 @@
-use reqwest::blocking::Client;
-use std::cmp;
-use std::path::Path;
-
-mod api;
-mod logic;
-mod models;
-
-use models::api_models::{ExchangeRecord, ExchangeSnapshot};
-use models::logic_models::TradingCurrencyRates;
-
-fn get_freshest_data(
-    most_recent_epoch: u64,
-    list_cached_snapshots: &[std::fs::DirEntry],
-    client: &Client,
-    data_path: &Path,
-) -> Vec<ExchangeRecord> {
-    if logic::check_if_snapshot_exists(most_recent_epoch, list_cached_snapshots) {
-        println!(
-            "We have the most recent snapshot, number {}",
-            &most_recent_epoch
-        );
-        let filename = format!("response_{}.json", &most_recent_epoch);
-        let json_file: std::fs::File =
-            std::fs::File::open(data_path.join(filename)).expect("Couldn't open json: ");
-        let reader: std::io::BufReader<std::fs::File> = std::io::BufReader::new(json_file);
-        serde_json::from_reader(reader).expect("Couldn't deserialize json: ")
-    } else {
-        println!("We do not have the most recent snapshot, getting newest pairs");
-        let fresh_data =
-            api::get_newest_snapshot_pairs(client).expect("Couldn't get newest set of pairs: ");
-        // After we get them cache them to disk so we don't get banned from the api
-        let filename = format!("response_{}.json", &most_recent_epoch);
-        logic::cache_to_disk(&fresh_data, data_path, &filename)
-            .expect("Couldn't cache snapshot to disk:");
-        fresh_data
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use poe_flip_finder::alert::Alerter;
+use poe_flip_finder::cache::SnapshotCache;
+use poe_flip_finder::cache::prune;
+use poe_flip_finder::config::{self, Config};
+use poe_flip_finder::models::logic_models::AnalysisParams;
+use poe_flip_finder::output::{self, OutputFormat, OutputOptions};
+use poe_flip_finder::run::{self, SnapshotChoice};
+use poe_flip_finder::source::{self, MarketSource, Poe2Scout, SqliteHistory};
+use poe_flip_finder::{api, backtest, cache, db, diff, export, logic, stats, watch};
+
+#[derive(Parser)]
+#[command(about = "Digs up triple arbitrage flips on the POE2 currency exchange")]
+struct Cli {
+    /// Path to the config file, it's fine if it doesn't exist
+    #[arg(long, default_value = "config.toml")]
+    config: PathBuf,
+    /// Don't make any http calls, analyze what's already in the cache
+    #[arg(long)]
+    offline: bool,
+    /// Analyze this cached snapshot instead of the newest one, implies --offline
+    #[arg(long, value_name = "EPOCH")]
+    snapshot: Option<u64>,
+    /// Analyze any snapshot json (plain or compressed), implies --offline
+    #[arg(long, value_name = "PATH", conflicts_with = "snapshot")]
+    file: Option<PathBuf>,
+    /// Play back responses saved by `record` instead of calling poe2scout
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["offline", "snapshot", "file"])]
+    replay: Option<PathBuf>,
+    /// Analyze a made up market from this seed, with a few mispricings
+    /// planted in it. Doesn't touch the network, cache or database.
+    #[arg(long, value_name = "SEED", conflicts_with_all = ["offline", "snapshot", "file", "replay"])]
+    demo: Option<u64>,
+    #[command(flatten)]
+    output: OutputOptions,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Look at or clean up the snapshot cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Dump raw snapshot pairs to csv
+    Export(ExportArgs),
+    /// Show which opportunities appeared, disappeared or moved between two
+    /// cached snapshots
+    Diff {
+        /// The older snapshot
+        from: u64,
+        /// The newer snapshot
+        to: u64,
+    },
+    /// Replay stored snapshots and see how the opportunities would have done
+    Backtest(BacktestArgs),
+    /// How long each bridge's mispricings last and how fast they revert
+    Stats {
+        /// Use the opportunities saved in the database instead of
+        /// re-analyzing the cache
+        #[arg(long)]
+        db: bool,
+    },
+    /// Save poe2scout's current responses so --replay can play them back
+    Record {
+        /// Directory to save them in
+        dir: PathBuf,
+    },
+    /// Keep polling for new snapshots and print opportunities as they change
+    Watch {
+        /// Seconds between polls, overrides the config
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Export this cached snapshot
+    #[arg(long, required_unless_present = "from", conflicts_with_all = ["from", "to"])]
+    epoch: Option<u64>,
+    /// Export everything stored in the database from this unix time on
+    #[arg(long, requires = "to")]
+    from: Option<u64>,
+    /// ...up to this unix time
+    #[arg(long, requires = "from")]
+    to: Option<u64>,
+    /// Database to read from, defaults to the one in the config
+    #[arg(long)]
+    db: Option<PathBuf>,
+    /// Where to write the csv, stdout if not given
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BacktestArgs {
+    /// Replay the snapshots in the database instead of the cache
+    #[arg(long)]
+    db: bool,
+    /// Override the minimum pair volume
+    #[arg(long)]
+    min_vol: Option<f64>,
+    /// Override how far off the base rate a route has to be, as a fraction
+    #[arg(long)]
+    min_profit: Option<f64>,
+    /// How many exalts to put through each opportunity
+    #[arg(long, default_value_t = 100.0)]
+    stake: f64,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List every cached snapshot
+    List,
+    /// Delete snapshots the retention policy doesn't want anymore
+    Prune {
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Override the size cap from the config
+        #[arg(long)]
+        max_size_mb: Option<u64>,
+        /// Also delete snapshots that are already in the database
+        #[arg(long)]
+        delete_imported: bool,
+    },
+}
+
+fn run_cache_command(command: CacheCommand, config: &Config) {
+    let mut cache = SnapshotCache::open(&config.data_dir).expect("Couldn't open snapshot cache: ");
+    let mut stdout = std::io::stdout().lock();
+    match command {
+        CacheCommand::List => {
+            cache::write_listing(&mut stdout, &cache).expect("Couldn't write output: ")
+        }
+        CacheCommand::Prune {
+            dry_run,
+            max_size_mb,
+            delete_imported,
+        } => {
+            let mut policy = config.cache.retention.clone();
+            if max_size_mb.is_some() {
+                policy.max_size_mb = max_size_mb;
+            }
+            policy.delete_imported |= delete_imported;
+            let actions = cache
+                .prune(&policy, dry_run)
+                .expect("Couldn't prune cache: ");
+            if dry_run {
+                println!("Dry run, nothing was actually deleted");
+            }
+            prune::write_prune_actions(&mut stdout, &actions, dry_run)
+                .expect("Couldn't print prune results: ");
+        }
     }
 }
 
 fn main() {
-    let client: Client = reqwest::blocking::Client::builder()
-        .user_agent("poe-flip-finder/1.0-camiam144@gmail.com")
-        .build()
-        .expect("Couldn't build client: ");
-
-    let most_recent_snapshot: ExchangeSnapshot = api::get_exchange_snapshot(&client).unwrap();
+    let cli = Cli::parse();
+    let config = config::load_config(&cli.config).expect("Couldn't load config: ");
 
-    println!(
-        "Most recent snapshot number: {}",
-        &most_recent_snapshot.epoch
-    );
+    match cli.command {
+        None => run_analysis(&cli, &config),
+        Some(Command::Cache { command }) => run_cache_command(command, &config),
+        Some(Command::Export(args)) => run_export(&args, &config),
+        Some(Command::Diff { from, to }) => run_diff(from, to, &cli.output, &config),
+        Some(Command::Backtest(args)) => run_backtest(&args, &cli.output, &config),
+        Some(Command::Stats { db }) => run_stats(db, &cli.output, &config),
+        Some(Command::Record { dir }) => run_record(&dir),
+        Some(Command::Watch { interval }) => run_watch(interval, &cli.output, config),
+    }
+}
 
-    let data_path: &Path = Path::new("data");
+fn run_record(dir: &Path) {
+    let client = api::build_client().expect("Couldn't build client: ");
+    let epoch = source::record(&client, dir).expect("Couldn't record responses: ");
+    eprintln!("Recorded snapshot {} to {}", epoch, dir.display());
+}
 
-    let cached_snapshots: Vec<std::fs::DirEntry> = logic::list_all_snapshots(data_path);
+fn run_watch(interval: Option<u64>, options: &OutputOptions, mut config: Config) {
+    if options.format == OutputFormat::Csv {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "watch can't print csv, use --format ndjson instead",
+            )
+            .exit();
+    }
+    if let Some(interval) = interval {
+        config.watch.interval_secs = interval;
+    }
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_flag = running.clone();
+    ctrlc::set_handler(move || handler_flag.store(false, Ordering::SeqCst))
+        .expect("Couldn't set Ctrl-C handler: ");
 
-    let newest_pairs: Vec<ExchangeRecord> = get_freshest_data(
-        most_recent_snapshot.epoch,
-        &cached_snapshots,
-        &client,
-        data_path,
-    );
+    let client = api::build_client().expect("Couldn't build client: ");
+    // Clients share their connection pool, so the alerts can ride on the same one
+    let alerter = Alerter::new(&config.alert, client.clone());
+    let source = Poe2Scout::new(client);
+    let mut cache = run::open_cache(&config).expect("Couldn't open snapshot cache: ");
 
-    // These are the base rates we need to compare against.
-    let mut base_rates: TradingCurrencyRates = TradingCurrencyRates::default();
+    if let Err(e) = watch::watch(
+        &source,
+        &mut cache,
+        &config,
+        &AnalysisParams::default(),
+        options,
+        alerter,
+        running,
+    ) {
+        exit_with("Couldn't write output, stopping", e);
+    }
+}
 
-    logic::get_base_prices(&newest_pairs, &mut base_rates);
+fn run_diff(from: u64, to: u64, options: &OutputOptions, config: &Config) {
+    let cache = SnapshotCache::open(&config.data_dir).expect("Couldn't open snapshot cache: ");
+    let diff = diff::diff_snapshots(&cache, from, to, &AnalysisParams::default())
+        .unwrap_or_else(|e| exit_with("Couldn't diff snapshots", e));
+    let mut stdout = std::io::stdout().lock();
+    output::write_diff(&mut stdout, options, from, to, &diff).expect("Couldn't write output: ");
+}
 
-    println!("Divine to Exalt ratio {:?}", &base_rates.div_to_exalt);
-    println!("Divine to Chaos ratio {:?}", &base_rates.div_to_chaos);
-    println!("Chaos to Exalt ratio {:?}", &base_rates.chaos_to_exalt);
+fn run_stats(db: bool, options: &OutputOptions, config: &Config) {
+    let stats = if db {
+        let conn =
+            rusqlite::Connection::open(&config.database_path).expect("Couldn't open database: ");
+        // Older databases don't have analyzed_snapshots yet
+        db::ensure_opportunity_schema(&conn).expect("Couldn't update database: ");
+        let snapshots = db::get_opportunity_history(&conn).expect("Couldn't query database: ");
+        stats::persistence_stats(snapshots)
+    } else {
+        let cache = SnapshotCache::open(&config.data_dir).expect("Couldn't open snapshot cache: ");
+        stats::source_stats(&cache, &AnalysisParams::default()).expect("Couldn't read the cache: ")
+    };
 
-    // These will be configurable via cmd line at some point probably
-    let min_vol: f64 = 10000.0;
+    let mut stdout = std::io::stdout().lock();
+    output::write_stats(&mut stdout, options, &stats).expect("Couldn't write output: ");
+}
 
-    let valid_bridges: Vec<ExchangeRecord> = newest_pairs
-        .into_iter()
-        .filter(|exch| exch.volume >= min_vol && exch.is_valid_bridge())
-        .collect();
+fn run_backtest(args: &BacktestArgs, options: &OutputOptions, config: &Config) {
+    let mut params = AnalysisParams::default();
+    if let Some(min_vol) = args.min_vol {
+        params.min_vol = min_vol;
+    }
+    if let Some(min_profit) = args.min_profit {
+        params.min_profit_frac = min_profit;
+    }
 
-    let (hub_to_bridge, bridge_to_hub) = logic::build_hub_bridge_maps(&valid_bridges);
+    let history: Box<dyn MarketSource> = if args.db {
+        let conn =
+            rusqlite::Connection::open(&config.database_path).expect("Couldn't open database: ");
+        Box::new(SqliteHistory::new(conn))
+    } else {
+        Box::new(SnapshotCache::open(&config.data_dir).expect("Couldn't open snapshot cache: "))
+    };
+    let report = backtest::backtest_source(history.as_ref(), &params, args.stake)
+        .expect("Couldn't replay snapshots: ");
 
-    let mut potential_profits = logic::build_bridges(&hub_to_bridge, &bridge_to_hub);
-    let min_profit_frac = 0.05;
+    let mut stdout = std::io::stdout().lock();
+    output::write_backtest(&mut stdout, options, &report).expect("Couldn't write output: ");
+}
 
-    potential_profits.retain(|elem| logic::eval_profit(elem, &base_rates, min_profit_frac));
-    potential_profits.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap());
+fn run_export(args: &ExportArgs, config: &Config) {
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path).expect("Couldn't create output file: ")),
+        None => Box::new(std::io::stdout().lock()),
+    };
 
-    let num_elements: usize = 10;
-    let end_idx = cmp::min(num_elements, potential_profits.len());
+    if let Some(epoch) = args.epoch {
+        let cache = SnapshotCache::open(&config.data_dir).expect("Couldn't open snapshot cache: ");
+        let records = cache
+            .load(epoch)
+            .unwrap_or_else(|e| exit_with("Couldn't export", e));
+        export::write_records_csv(out, &records).expect("Couldn't write csv: ");
+    } else if let (Some(from), Some(to)) = (args.from, args.to) {
+        let db_path = args.db.as_ref().unwrap_or(&config.database_path);
+        let conn = rusqlite::Connection::open(db_path).expect("Couldn't open database: ");
+        let exported = export::export_range(&conn, from, to, out)
+            .unwrap_or_else(|e| exit_with("Couldn't export", e));
+        eprintln!("Exported {} rows from {}", exported, db_path.display());
+    }
+}
 
-    // What I actually want here are either the highest margin items or the
-    // top N items from each bridge
-    println!("Top vals:");
-    for elem in &potential_profits[..end_idx] {
-        println!(
-            "Hub 1 {} -> Bridge {} -> Hub 2 {} | normalized exalt price  {}",
-            elem.0, elem.1, elem.2, elem.3
-        )
+fn snapshot_choice(cli: &Cli) -> SnapshotChoice {
+    if let Some(path) = &cli.file {
+        SnapshotChoice::File(path.clone())
+    } else if let Some(seed) = cli.demo {
+        SnapshotChoice::Demo(seed)
+    } else if cli.offline || cli.snapshot.is_some() {
+        SnapshotChoice::Cached(cli.snapshot)
+    } else if let Some(dir) = &cli.replay {
+        SnapshotChoice::Replay(dir.clone())
+    } else {
+        SnapshotChoice::Live
     }
+}
 
-    potential_profits.reverse();
-    // Are the really low vals also an option, but in reverse?
-    println!("Bottom vals:");
-    for elem in &potential_profits[..end_idx] {
-        println!(
-            "Hub 1 {} -> Bridge {} -> Hub 2 {} | normalized exalt price {}",
-            elem.0, elem.1, elem.2, elem.3
-        )
+fn run_analysis(cli: &Cli, config: &Config) {
+    let choice = snapshot_choice(cli);
+    let (records, metadata) = run::get_snapshot(&choice, config)
+        .unwrap_or_else(|e| exit_with("Couldn't get a snapshot to analyze", e));
+    let params = AnalysisParams::default();
+    let analysis = logic::analyze(records, metadata.snapshot_epoch, &params);
+
+    output::write_dropped(&mut std::io::stderr(), &analysis.validation_report)
+        .expect("Couldn't write output: ");
+    // A made up market isn't worth keeping
+    if !matches!(choice, SnapshotChoice::Demo(_)) {
+        run::save_opportunities(config, metadata.snapshot_epoch, &analysis.opportunities);
     }
+
+    let mut stdout = std::io::stdout().lock();
+    output::write_analysis(&mut stdout, &cli.output, &analysis, &metadata, &params)
+        .expect("Couldn't write output: ");
+}
+
+// For errors that are the user's to fix, like asking for a snapshot we don't
+// have, a message reads better than a panic
+fn exit_with(what: &str, e: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", what, e);
+    std::process::exit(1);
 }
@@ -1,2 +1,5 @@
+//! Types shared across the crate. `api_models` is what poe2scout sends us,
+//! `logic_models` is what we work out from it.
+
 pub mod api_models;
 pub mod logic_models;
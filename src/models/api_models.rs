@@ -5,7 +5,8 @@ use serde_json::Value;
 
 use crate::models::logic_models::TradingCurrencyType;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// One currency pair in a snapshot, as poe2scout sends it
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct ExchangeRecord {
     #[serde(rename = "CurrencyExchangeSnapshotPairId")]
@@ -39,6 +40,7 @@ fn str_as_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Erro
 }
 
 impl ExchangeRecord {
+    /// Which hubs the two sides are, `Other` for anything that isn't one
     pub fn trading_currency(&self) -> (TradingCurrencyType, TradingCurrencyType) {
         let curr1 = TradingCurrencyType::from_str(&self.currency_one.text).unwrap();
         let curr2 = TradingCurrencyType::from_str(&self.currency_two.text).unwrap();
@@ -46,13 +48,16 @@ impl ExchangeRecord {
         (curr1, curr2)
     }
 
+    /// Exactly one side is a hub, so the other can be a bridge
     pub fn is_valid_bridge(&self) -> bool {
         let (curr1, curr2) = self.trading_currency();
         (curr1 != TradingCurrencyType::Other && curr2 == TradingCurrencyType::Other)
             || (curr1 == TradingCurrencyType::Other && curr2 != TradingCurrencyType::Other)
     }
 
-    pub fn hub_bridge_price(&self) -> Option<(TradingCurrencyType, f64, String, f64)> {
+    /// The hub, its price, the bridge's name and its price, whichever way
+    /// round the pair is listed
+    pub fn hub_bridge_price(&self) -> Option<(TradingCurrencyType, f64, &str, f64)> {
         // Get the price of the hub -> bridge or bridge -> hub in a manner
         // that is easier to work with
         let (c1, c2) = self.trading_currency();
@@ -67,7 +72,7 @@ impl ExchangeRecord {
             ) => Some((
                 hub,
                 self.currency_one_data.relative_price,
-                self.currency_two.text.clone(),
+                &self.currency_two.text,
                 self.currency_two_data.relative_price,
             )),
             // bridge -> hub
@@ -79,7 +84,7 @@ impl ExchangeRecord {
             ) => Some((
                 hub,
                 self.currency_two_data.relative_price,
-                self.currency_one.text.clone(),
+                &self.currency_one.text,
                 self.currency_one_data.relative_price,
             )),
             _ => None,
@@ -87,8 +92,8 @@ impl ExchangeRecord {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// What the currency is
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct CurrencyInfo {
     pub id: u64,
@@ -100,8 +105,8 @@ pub struct CurrencyInfo {
     pub icon_url: String,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// How it's trading in this pair. relative_price is in exalts.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct CurrencyData {
     pub highest_stock: u64,
@@ -114,8 +119,8 @@ pub struct CurrencyData {
     pub volume_traded: u64,
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
+/// A row of the `exchange_rates` table
+#[derive(Debug, Serialize)]
 pub struct ExchangeQueryResult {
     pub ts: u64,
     pub pair_id: u64,
@@ -127,6 +132,26 @@ pub struct ExchangeQueryResult {
     pub volume: f64,
 }
 
+// The database only has the names, prices and volume, which is all the
+// analysis looks at anyway
+impl From<ExchangeQueryResult> for ExchangeRecord {
+    fn from(row: ExchangeQueryResult) -> Self {
+        let mut record = ExchangeRecord {
+            pair_id: row.pair_id,
+            snapshot_id: row.snapshot_id,
+            volume: row.volume,
+            ..Default::default()
+        };
+        record.currency_one.text = row.from_currency;
+        record.currency_two.text = row.to_currency;
+        record.currency_one_data.relative_price = row.from_relative_price;
+        record.currency_two_data.relative_price = row.to_relative_price;
+        record
+    }
+}
+
+/// The snapshot metadata poe2scout hands back, the epoch is what we key the
+/// cache on
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ExchangeSnapshot {
@@ -1,6 +1,11 @@
 use std::{convert::Infallible, fmt, str::FromStr};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// The three currencies we trade through bridges between, and everything
+/// else
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum TradingCurrencyType {
     Exalt,
     Chaos,
@@ -30,13 +35,204 @@ impl fmt::Display for TradingCurrencyType {
     }
 }
 
-#[derive(Debug, Default)]
+/// The direct rates between the hubs
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct TradingCurrencyRates {
     pub div_to_exalt: f64,
     pub div_to_chaos: f64,
     pub chaos_to_exalt: f64,
 }
 
+impl TradingCurrencyRates {
+    fn exalt_value(&self, currency: TradingCurrencyType) -> Option<f64> {
+        match currency {
+            TradingCurrencyType::Exalt => Some(1.0),
+            TradingCurrencyType::Chaos => Some(self.chaos_to_exalt),
+            TradingCurrencyType::Divine => Some(self.div_to_exalt),
+            TradingCurrencyType::Other => None,
+        }
+    }
+
+    /// How many `to` you get for one `from` at the base rates, in either
+    /// direction between any two hubs.
+    pub fn reference_rate(
+        &self,
+        from: TradingCurrencyType,
+        to: TradingCurrencyType,
+    ) -> Option<f64> {
+        Some(self.exalt_value(from)? / self.exalt_value(to)?)
+    }
+}
+
+/// A bridge currency's number in [`crate::logic::BridgeMaps`], so the route
+/// search can pass it around without cloning or hashing the name
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BridgeId(pub usize);
+
+/// One hub -> bridge -> hub route [`crate::logic::build_bridges`] found,
+/// before it's been checked against the base rates
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BridgeRoute {
+    pub hub_one: TradingCurrencyType,
+    pub bridge: BridgeId,
+    pub hub_two: TradingCurrencyType,
+    /// How many hub_two one hub_one gets you going through the bridge
+    pub implied_rate: f64,
+}
+
+/// Which way round to run a route to come out ahead
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// hub_one -> bridge -> hub_two, then back to hub_one at the base rate
+    Forward,
+    /// hub_two -> bridge -> hub_one, then back to hub_two at the base rate
+    Reverse,
+}
+
+impl Direction {
+    /// Positive margins run forward, negative ones in reverse
+    pub fn from_margin(margin: f64) -> Self {
+        if margin < 0.0 {
+            Direction::Reverse
+        } else {
+            Direction::Forward
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Forward => write!(f, "forward"),
+            Direction::Reverse => write!(f, "reverse"),
+        }
+    }
+}
+
+/// A bridge that's far enough off the base rate to be worth a look.
+/// hub_one -> bridge -> hub_two gets you implied_rate hub_two per hub_one,
+/// where the direct trade gets you reference_rate.
+#[derive(Debug, Clone, Serialize)]
+pub struct Opportunity {
+    pub hub_one: TradingCurrencyType,
+    pub bridge: String,
+    pub hub_two: TradingCurrencyType,
+    pub implied_rate: f64,
+    pub reference_rate: f64,
+    /// (implied - reference) / reference, positive means going through the
+    /// bridge beats the direct trade
+    pub margin: f64,
+    /// Always [`Direction::from_margin`] of the margin, spelled out for
+    /// whoever's reading the json
+    pub direction: Direction,
+    pub hub_one_volume: f64,
+    pub hub_two_volume: f64,
+    pub snapshot_epoch: Option<u64>,
+}
+
+/// What makes two opportunities from different snapshots "the same one"
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct RouteKey {
+    pub hub_one: TradingCurrencyType,
+    pub bridge: String,
+    pub hub_two: TradingCurrencyType,
+}
+
+impl Opportunity {
+    /// The hubs and bridge, for matching it up across snapshots
+    pub fn route(&self) -> RouteKey {
+        RouteKey {
+            hub_one: self.hub_one,
+            bridge: self.bridge.clone(),
+            hub_two: self.hub_two,
+        }
+    }
+
+    /// A Divine -> bridge -> Exalt route off a 300 ex divine, for tests
+    #[cfg(test)]
+    pub(crate) fn sample(bridge: &str, margin: f64) -> Opportunity {
+        Opportunity {
+            hub_one: TradingCurrencyType::Divine,
+            bridge: bridge.to_string(),
+            hub_two: TradingCurrencyType::Exalt,
+            implied_rate: 300.0 * (1.0 + margin),
+            reference_rate: 300.0,
+            margin,
+            direction: Direction::from_margin(margin),
+            hub_one_volume: 20000.0,
+            hub_two_volume: 15000.0,
+            snapshot_epoch: Some(1),
+        }
+    }
+}
+
+/// How to pick the "best" opportunities out of everything we found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankStrategy {
+    /// Biggest margins overall, either direction
+    Margin,
+    /// Top N for each pair of hubs
+    HubPair,
+    /// Top N for each bridge currency
+    Bridge,
+    /// Biggest margin times the volume we could actually push through
+    Profit,
+}
+
+/// Knobs for the opportunity search
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisParams {
+    /// Pairs with less volume than this aren't worth bridging through
+    pub min_vol: f64,
+    /// How far off the base rate a bridge has to be, as a fraction of it
+    pub min_profit_frac: f64,
+}
+
+impl Default for AnalysisParams {
+    fn default() -> Self {
+        AnalysisParams {
+            min_vol: 10000.0,
+            min_profit_frac: 0.05,
+        }
+    }
+}
+
+/// Why validation threw a record out
+#[derive(Debug, Clone, PartialEq)]
+pub enum DropReason {
+    BadPrice(f64),
+    DuplicatePairId,
+    SelfPair,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DropReason::BadPrice(price) => write!(f, "bad relative price {}", price),
+            DropReason::DuplicatePairId => write!(f, "duplicate pair id"),
+            DropReason::SelfPair => write!(f, "currency paired with itself"),
+        }
+    }
+}
+
+/// A record validation threw out, with enough to find it again
+#[derive(Debug, Clone)]
+pub struct DroppedRecord {
+    pub pair_id: u64,
+    pub currency_one: String,
+    pub currency_two: String,
+    pub reason: DropReason,
+}
+
+/// What validation kept and what it dropped
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub kept: usize,
+    pub dropped: Vec<DroppedRecord>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,6 +253,30 @@ mod tests {
         assert_eq!(orb.unwrap(), TradingCurrencyType::Chaos)
     }
     #[test]
+    fn test_reference_rate() {
+        let rates = TradingCurrencyRates {
+            div_to_exalt: 300.0,
+            div_to_chaos: 25.0,
+            chaos_to_exalt: 12.0,
+        };
+        let div_chaos =
+            rates.reference_rate(TradingCurrencyType::Divine, TradingCurrencyType::Chaos);
+        assert_eq!(div_chaos, Some(25.0));
+        let exalt_div =
+            rates.reference_rate(TradingCurrencyType::Exalt, TradingCurrencyType::Divine);
+        assert_eq!(exalt_div, Some(1.0 / 300.0));
+        assert_eq!(
+            rates.reference_rate(TradingCurrencyType::Other, TradingCurrencyType::Exalt),
+            None
+        );
+    }
+    #[test]
+    fn test_direction_from_margin() {
+        assert_eq!(Direction::from_margin(0.1), Direction::Forward);
+        assert_eq!(Direction::from_margin(-0.1), Direction::Reverse);
+        assert_eq!(Direction::Reverse.to_string(), "reverse");
+    }
+    #[test]
     fn test_parse_other() {
         let orb = TradingCurrencyType::from_str("Vaal Orb");
         assert_eq!(orb.unwrap(), TradingCurrencyType::Other)
@@ -0,0 +1,778 @@
+use std::io::{self, IsTerminal, Write};
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+
+pub mod table;
+
+use table::Column;
+
+use crate::backtest::{BacktestReport, BacktestSummary, Trade};
+use crate::diff::{self, OpportunityDiff, Streaks};
+use crate::logic::{self, Analysis};
+use crate::models::logic_models::{
+    AnalysisParams, Opportunity, RankStrategy, TradingCurrencyRates, TradingCurrencyType,
+    ValidationReport,
+};
+use crate::stats::BridgeStats;
+
+// How many rows the table shows when --top isn't given
+const DEFAULT_TABLE_ROWS: usize = 10;
+
+// Bump this whenever a field in the json output changes meaning or goes away.
+// Adding fields is fine without a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// The csv columns for each kind of row, spelled out so an empty result still
+// gets a header. They have to match the field names serde writes.
+const OPPORTUNITY_COLUMNS: &[&str] = &[
+    "hub_one",
+    "bridge",
+    "hub_two",
+    "implied_rate",
+    "reference_rate",
+    "margin",
+    "direction",
+    "hub_one_volume",
+    "hub_two_volume",
+    "snapshot_epoch",
+];
+const DIFF_COLUMNS: &[&str] = &[
+    "change",
+    "hub_one",
+    "bridge",
+    "hub_two",
+    "previous_margin",
+    "margin",
+];
+const TRADE_COLUMNS: &[&str] = &[
+    "signal_epoch",
+    "executed_epoch",
+    "hub_one",
+    "bridge",
+    "hub_two",
+    "expected_return",
+    "realized_return",
+    "pnl",
+];
+const STATS_COLUMNS: &[&str] = &[
+    "bridge",
+    "runs",
+    "ongoing",
+    "mean_run_length",
+    "longest_run",
+    "median_revert_secs",
+    "median_revert_snapshots",
+    "grew",
+    "shrank",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[value(alias = "text")]
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct OutputOptions {
+    /// How to print the results, json, ndjson and csv are for scripts
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+    /// Which columns the table shows, comma separated
+    #[arg(long, global = true, value_enum, value_delimiter = ',', default_values_t = table::DEFAULT_COLUMNS.to_vec())]
+    pub columns: Vec<Column>,
+    /// How to pick which opportunities to show
+    #[arg(long, global = true, value_enum, default_value_t = RankStrategy::Margin)]
+    pub rank: RankStrategy,
+    /// How many to show (per group for hub-pair and bridge). The table
+    /// defaults to 10, the other formats to everything
+    #[arg(long, global = true)]
+    pub top: Option<usize>,
+    /// Color the table by margin, auto turns it off when piped
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+}
+
+impl OutputOptions {
+    pub fn use_color(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+        }
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: OutputFormat::Table,
+            columns: table::DEFAULT_COLUMNS.to_vec(),
+            rank: RankStrategy::Margin,
+            top: None,
+            color: ColorChoice::Auto,
+        }
+    }
+}
+
+// Where the snapshot we analyzed came from, goes in the header so a script
+// can tell a stale offline run from a fresh one.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunMetadata {
+    pub snapshot_epoch: Option<u64>,
+    pub source: String,
+    pub generated_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunHeader<'a> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub metadata: &'a RunMetadata,
+    pub base_rates: &'a TradingCurrencyRates,
+    pub params: &'a AnalysisParams,
+    pub dropped_records: usize,
+    // Everything we found, before ranking cut it down
+    pub opportunity_count: usize,
+    pub rank: RankStrategy,
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    header: RunHeader<'a>,
+    opportunities: &'a [&'a Opportunity],
+}
+
+// One object per line, the header first so streaming readers get the
+// context before the opportunities
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum NdjsonLine<'a> {
+    Header(RunHeader<'a>),
+    Opportunity(&'a Opportunity),
+}
+
+// Same "type": "opportunity" lines, plus how many snapshots in a row watch
+// has seen the route
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum UpdateLine<'a> {
+    Opportunity {
+        #[serde(flatten)]
+        opportunity: &'a Opportunity,
+        streak: u32,
+    },
+}
+
+pub fn write_analysis(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    analysis: &Analysis,
+    metadata: &RunMetadata,
+    params: &AnalysisParams,
+) -> io::Result<()> {
+    let header = RunHeader {
+        schema_version: SCHEMA_VERSION,
+        metadata,
+        base_rates: &analysis.base_rates,
+        params,
+        dropped_records: analysis.validation_report.dropped.len(),
+        opportunity_count: analysis.opportunities.len(),
+        rank: options.rank,
+    };
+    let top_n = match (options.top, options.format) {
+        (Some(top_n), _) => top_n,
+        (None, OutputFormat::Table) => DEFAULT_TABLE_ROWS,
+        (None, _) => usize::MAX,
+    };
+    let ranked = logic::rank_opportunities(&analysis.opportunities, options.rank, top_n);
+
+    match options.format {
+        OutputFormat::Table => write_tables(out, analysis, &ranked, options),
+        OutputFormat::Json => {
+            let output = JsonOutput {
+                header,
+                opportunities: &ranked,
+            };
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)
+        }
+        OutputFormat::Ndjson => {
+            serde_json::to_writer(&mut *out, &NdjsonLine::Header(header))?;
+            writeln!(out)?;
+            for opportunity in &ranked {
+                serde_json::to_writer(&mut *out, &NdjsonLine::Opportunity(opportunity))?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => write_csv(out, OPPORTUNITY_COLUMNS, &ranked).map_err(io::Error::other),
+    }
+}
+
+// The records validation threw out, goes to stderr ahead of the results
+pub fn write_dropped(out: &mut impl Write, report: &ValidationReport) -> io::Result<()> {
+    if report.dropped.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "Dropped {} bad records, kept {}:",
+        report.dropped.len(),
+        report.kept
+    )?;
+    for dropped in &report.dropped {
+        writeln!(
+            out,
+            "  pair {} ({} / {}): {}",
+            dropped.pair_id, dropped.currency_one, dropped.currency_two, dropped.reason
+        )?;
+    }
+    Ok(())
+}
+
+// What watch prints for each new snapshot. There's no single document to
+// wrap things in when we run forever, so json comes out as ndjson too. csv
+// would need its header repeated or remembered across updates, so watch
+// doesn't take it.
+pub fn write_update(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    epoch: u64,
+    diff: &OpportunityDiff,
+    changed: &[&Opportunity],
+    streaks: &Streaks,
+) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Table => {
+            writeln!(
+                out,
+                "\nSnapshot {}: {} appeared, {} disappeared, {} persisted ({} for {}+ snapshots)",
+                epoch,
+                diff.appeared.len(),
+                diff.disappeared.len(),
+                diff.persisted.len(),
+                streaks.trusted(),
+                diff::TRUSTED_STREAK
+            )?;
+            if changed.is_empty() {
+                return Ok(());
+            }
+            table::write_table(out, changed, &options.columns, options.use_color())
+        }
+        OutputFormat::Csv => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "watch can't write csv",
+        )),
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            for opportunity in changed {
+                let line = UpdateLine::Opportunity {
+                    opportunity,
+                    streak: streaks.get(&opportunity.route()),
+                };
+                serde_json::to_writer(&mut *out, &line)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DiffOutput<'a> {
+    schema_version: u32,
+    from_epoch: u64,
+    to_epoch: u64,
+    #[serde(flatten)]
+    diff: &'a OpportunityDiff,
+}
+
+// One flat row per route for ndjson and csv, the margins are empty on the
+// side the route is missing from
+#[derive(Serialize)]
+struct DiffRow<'a> {
+    change: &'static str,
+    hub_one: TradingCurrencyType,
+    bridge: &'a str,
+    hub_two: TradingCurrencyType,
+    previous_margin: Option<f64>,
+    margin: Option<f64>,
+}
+
+impl<'a> DiffRow<'a> {
+    fn new(change: &'static str, opportunity: &'a Opportunity) -> Self {
+        DiffRow {
+            change,
+            hub_one: opportunity.hub_one,
+            bridge: &opportunity.bridge,
+            hub_two: opportunity.hub_two,
+            previous_margin: None,
+            margin: None,
+        }
+    }
+}
+
+fn diff_rows(diff: &OpportunityDiff) -> Vec<DiffRow<'_>> {
+    let appeared = diff.appeared.iter().map(|o| DiffRow {
+        margin: Some(o.margin),
+        ..DiffRow::new("appeared", o)
+    });
+    let disappeared = diff.disappeared.iter().map(|o| DiffRow {
+        previous_margin: Some(o.margin),
+        ..DiffRow::new("disappeared", o)
+    });
+    let persisted = diff.persisted.iter().map(|p| DiffRow {
+        previous_margin: Some(p.previous_margin),
+        margin: Some(p.opportunity.margin),
+        ..DiffRow::new("persisted", &p.opportunity)
+    });
+    appeared.chain(disappeared).chain(persisted).collect()
+}
+
+pub fn write_diff(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    from_epoch: u64,
+    to_epoch: u64,
+    diff: &OpportunityDiff,
+) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Table => {
+            writeln!(
+                out,
+                "Snapshot {} -> {}: {} appeared, {} disappeared, {} persisted",
+                from_epoch,
+                to_epoch,
+                diff.appeared.len(),
+                diff.disappeared.len(),
+                diff.persisted.len()
+            )?;
+            let color = options.use_color();
+            for (title, opportunities) in [
+                ("Appeared", &diff.appeared),
+                ("Disappeared", &diff.disappeared),
+            ] {
+                if opportunities.is_empty() {
+                    continue;
+                }
+                writeln!(out, "\n{} ({}):", title, opportunities.len())?;
+                let opportunities: Vec<&Opportunity> = opportunities.iter().collect();
+                table::write_table(out, &opportunities, &options.columns, color)?;
+            }
+            if !diff.persisted.is_empty() {
+                writeln!(out, "\nPersisted ({}):", diff.persisted.len())?;
+                table::write_margin_changes(out, &diff.persisted, color)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let output = DiffOutput {
+                schema_version: SCHEMA_VERSION,
+                from_epoch,
+                to_epoch,
+                diff,
+            };
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)
+        }
+        OutputFormat::Ndjson => {
+            for row in diff_rows(diff) {
+                serde_json::to_writer(&mut *out, &row)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => {
+            write_csv(out, DIFF_COLUMNS, diff_rows(diff)).map_err(io::Error::other)
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BacktestLine<'a> {
+    Trade(&'a Trade),
+    Summary(&'a BacktestSummary),
+}
+
+pub fn write_backtest(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    report: &BacktestReport,
+) -> io::Result<()> {
+    let summary = &report.summary;
+    match options.format {
+        OutputFormat::Table => {
+            let range = match (summary.first_epoch, summary.last_epoch) {
+                (Some(first), Some(last)) => format!(" ({} to {})", first, last),
+                _ => String::new(),
+            };
+            writeln!(
+                out,
+                "Replayed {} snapshots{}, min_vol {}, min_profit_frac {}",
+                summary.snapshots, range, summary.params.min_vol, summary.params.min_profit_frac
+            )?;
+            writeln!(
+                out,
+                "Trades: {} ({} couldn't fill, the route was gone)",
+                summary.trade_count, summary.unfilled
+            )?;
+            writeln!(
+                out,
+                "P&L: {:+.2} ex at {} ex a trade",
+                summary.total_pnl, summary.stake
+            )?;
+            writeln!(out, "Hit rate: {:.1}%", summary.hit_rate * 100.0)?;
+            writeln!(out, "Max drawdown: {:.2} ex", summary.max_drawdown)
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, report)?;
+            writeln!(out)
+        }
+        OutputFormat::Ndjson => {
+            for trade in &report.trades {
+                serde_json::to_writer(&mut *out, &BacktestLine::Trade(trade))?;
+                writeln!(out)?;
+            }
+            serde_json::to_writer(&mut *out, &BacktestLine::Summary(summary))?;
+            writeln!(out)
+        }
+        OutputFormat::Csv => {
+            write_csv(out, TRADE_COLUMNS, &report.trades).map_err(io::Error::other)
+        }
+    }
+}
+
+pub fn write_stats(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    stats: &[BridgeStats],
+) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Table => {
+            let width = stats
+                .iter()
+                .map(|s| s.bridge.chars().count())
+                .chain(["Bridge".len()])
+                .max()
+                .unwrap_or(0);
+            let header = format!(
+                "{:<width$} | Runs | Ongoing | Avg run | Longest | {:>15} | Grew | Shrank",
+                "Bridge", "Median revert"
+            );
+            writeln!(out, "{}", header)?;
+            writeln!(out, "{}", "-".repeat(header.len()))?;
+            for s in stats {
+                let revert = match (s.median_revert_secs, s.median_revert_snapshots) {
+                    (Some(secs), Some(snapshots)) => {
+                        format!("{:.1}h / {} snaps", secs as f64 / 3600.0, snapshots)
+                    }
+                    _ => "-".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{:<width$} | {:>4} | {:>7} | {:>7.1} | {:>7} | {:>15} | {:>4} | {:>6}",
+                    s.bridge,
+                    s.runs,
+                    s.ongoing,
+                    s.mean_run_length,
+                    s.longest_run,
+                    revert,
+                    s.grew,
+                    s.shrank
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, stats)?;
+            writeln!(out)
+        }
+        OutputFormat::Ndjson => {
+            for s in stats {
+                serde_json::to_writer(&mut *out, s)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => write_csv(out, STATS_COLUMNS, stats).map_err(io::Error::other),
+    }
+}
+
+// csv only writes a header along with the first row, so on its own an empty
+// result comes out as an empty file. This writes the header up front instead.
+pub(crate) fn write_csv<T: Serialize>(
+    out: impl Write,
+    columns: &[&str],
+    rows: impl IntoIterator<Item = T>,
+) -> csv::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(out);
+    writer.write_record(columns)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// What csv would have used for the header given a row, for checking the
+// column lists above against the structs
+#[cfg(test)]
+pub(crate) fn serde_columns<T: Serialize>(row: T) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(row).unwrap();
+    let text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    text.lines().next().unwrap().to_string()
+}
+
+fn write_tables(
+    out: &mut impl Write,
+    analysis: &Analysis,
+    ranked: &[&Opportunity],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let base_rates = &analysis.base_rates;
+    writeln!(
+        out,
+        "Divine to Exalt {} | Divine to Chaos {} | Chaos to Exalt {}",
+        table::format_rate(base_rates.div_to_exalt),
+        table::format_rate(base_rates.div_to_chaos),
+        table::format_rate(base_rates.chaos_to_exalt)
+    )?;
+
+    let title = match options.rank {
+        RankStrategy::Margin => "by margin",
+        RankStrategy::HubPair => "per hub pair",
+        RankStrategy::Bridge => "per bridge",
+        RankStrategy::Profit => "by expected profit",
+    };
+    writeln!(
+        out,
+        "\nTop {} {} (found {}):",
+        options.top.unwrap_or(DEFAULT_TABLE_ROWS),
+        title,
+        analysis.opportunities.len()
+    )?;
+    table::write_table(out, ranked, &options.columns, options.use_color())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::logic_models::Direction;
+
+    fn analysis() -> Analysis {
+        Analysis {
+            validation_report: ValidationReport::default(),
+            base_rates: TradingCurrencyRates {
+                div_to_exalt: 300.0,
+                div_to_chaos: 25.0,
+                chaos_to_exalt: 12.0,
+            },
+            opportunities: vec![Opportunity {
+                hub_one: TradingCurrencyType::Divine,
+                bridge: "Vaal Orb".to_string(),
+                hub_two: TradingCurrencyType::Exalt,
+                implied_rate: 330.0,
+                reference_rate: 300.0,
+                margin: 0.1,
+                direction: Direction::from_margin(0.1),
+                hub_one_volume: 20000.0,
+                hub_two_volume: 15000.0,
+                snapshot_epoch: Some(42),
+            }],
+        }
+    }
+
+    fn metadata() -> RunMetadata {
+        RunMetadata {
+            snapshot_epoch: Some(42),
+            source: "cache".to_string(),
+            generated_at: 1000,
+        }
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let mut out = Vec::new();
+        let params = AnalysisParams::default();
+        let options = OutputOptions {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        write_analysis(&mut out, &options, &analysis(), &metadata(), &params).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let header = &value["header"];
+        assert_eq!(header["schema_version"], SCHEMA_VERSION);
+        assert_eq!(header["snapshot_epoch"], 42);
+        assert_eq!(header["source"], "cache");
+        assert_eq!(header["base_rates"]["div_to_exalt"], 300.0);
+        assert_eq!(header["opportunity_count"], 1);
+        assert_eq!(header["rank"], "margin");
+
+        let opportunity = &value["opportunities"][0];
+        assert_eq!(opportunity["hub_one"], "Divine");
+        assert_eq!(opportunity["bridge"], "Vaal Orb");
+        assert_eq!(opportunity["hub_two"], "Exalt");
+        assert_eq!(opportunity["margin"], 0.1);
+        assert_eq!(opportunity["hub_two_volume"], 15000.0);
+    }
+
+    #[test]
+    fn test_ndjson_header_first() {
+        let mut out = Vec::new();
+        let params = AnalysisParams::default();
+        let options = OutputOptions {
+            format: OutputFormat::Ndjson,
+            ..Default::default()
+        };
+        write_analysis(&mut out, &options, &analysis(), &metadata(), &params).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "header");
+        assert_eq!(lines[0]["schema_version"], SCHEMA_VERSION);
+        assert_eq!(lines[1]["type"], "opportunity");
+        assert_eq!(lines[1]["implied_rate"], 330.0);
+    }
+
+    #[test]
+    fn test_csv_has_one_row_per_opportunity() {
+        let mut out = Vec::new();
+        let params = AnalysisParams::default();
+        let options = OutputOptions {
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        write_analysis(&mut out, &options, &analysis(), &metadata(), &params).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "hub_one,bridge,hub_two,implied_rate,reference_rate,margin,direction,hub_one_volume,hub_two_volume,snapshot_epoch"
+        );
+        assert_eq!(
+            lines[1],
+            "Divine,Vaal Orb,Exalt,330.0,300.0,0.1,forward,20000.0,15000.0,42"
+        );
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_csv_columns_match_the_rows() {
+        let opportunity = &analysis().opportunities[0];
+        assert_eq!(serde_columns(opportunity), OPPORTUNITY_COLUMNS.join(","));
+        assert_eq!(
+            serde_columns(DiffRow::new("appeared", opportunity)),
+            DIFF_COLUMNS.join(",")
+        );
+        let trade = Trade {
+            signal_epoch: 1,
+            executed_epoch: 2,
+            hub_one: TradingCurrencyType::Divine,
+            bridge: "Vaal Orb".to_string(),
+            hub_two: TradingCurrencyType::Exalt,
+            expected_return: 0.1,
+            realized_return: 0.05,
+            pnl: 5.0,
+        };
+        assert_eq!(serde_columns(trade), TRADE_COLUMNS.join(","));
+        assert_eq!(
+            serde_columns(BridgeStats::default()),
+            STATS_COLUMNS.join(",")
+        );
+    }
+
+    #[test]
+    fn test_empty_csv_still_has_a_header() {
+        let mut empty = analysis();
+        empty.opportunities.clear();
+        let options = OutputOptions {
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_analysis(
+            &mut out,
+            &options,
+            &empty,
+            &metadata(),
+            &AnalysisParams::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}\n", OPPORTUNITY_COLUMNS.join(","))
+        );
+
+        let mut out = Vec::new();
+        write_stats(&mut out, &options, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}\n", STATS_COLUMNS.join(","))
+        );
+    }
+
+    #[test]
+    fn test_update_refuses_csv() {
+        let opportunities = analysis().opportunities;
+        let diff = diff::diff_opportunities(&[], &opportunities);
+        let changed: Vec<&Opportunity> = opportunities.iter().collect();
+        let options = OutputOptions {
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let result = write_update(&mut out, &options, 42, &diff, &changed, &Streaks::default());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // Rather than quietly writing ndjson instead
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_diff_csv_rows() {
+        let before = analysis().opportunities;
+        let mut after = before.clone();
+        after[0].margin = 0.2;
+        after.push(Opportunity {
+            bridge: "Regal Orb".to_string(),
+            ..after[0].clone()
+        });
+        let diff = diff::diff_opportunities(&before, &after);
+
+        let mut out = Vec::new();
+        let options = OutputOptions {
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        write_diff(&mut out, &options, 41, 42, &diff).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "change,hub_one,bridge,hub_two,previous_margin,margin",
+                "appeared,Divine,Regal Orb,Exalt,,0.2",
+                "persisted,Divine,Vaal Orb,Exalt,0.1,0.2",
+            ]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+
+use crate::diff::PersistedOpportunity;
+use crate::models::logic_models::{Direction, Opportunity};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Column {
+    Hub1,
+    Bridge,
+    Hub2,
+    Implied,
+    Reference,
+    Margin,
+    Direction,
+    Vol1,
+    Vol2,
+    Epoch,
+}
+
+pub const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Hub1,
+    Column::Bridge,
+    Column::Hub2,
+    Column::Implied,
+    Column::Reference,
+    Column::Margin,
+    Column::Vol1,
+    Column::Vol2,
+];
+
+impl Column {
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Hub1 => "Hub 1",
+            Column::Bridge => "Bridge",
+            Column::Hub2 => "Hub 2",
+            Column::Implied => "Implied",
+            Column::Reference => "Reference",
+            Column::Margin => "Margin",
+            Column::Direction => "Direction",
+            Column::Vol1 => "Vol 1",
+            Column::Vol2 => "Vol 2",
+            Column::Epoch => "Snapshot",
+        }
+    }
+
+    fn right_aligned(&self) -> bool {
+        !matches!(
+            self,
+            Column::Hub1 | Column::Bridge | Column::Hub2 | Column::Direction
+        )
+    }
+
+    fn cell(&self, opportunity: &Opportunity) -> String {
+        match self {
+            Column::Hub1 => opportunity.hub_one.to_string(),
+            Column::Bridge => opportunity.bridge.clone(),
+            Column::Hub2 => opportunity.hub_two.to_string(),
+            Column::Implied => format_rate(opportunity.implied_rate),
+            Column::Reference => format_rate(opportunity.reference_rate),
+            Column::Margin => format!("{:+.2}%", opportunity.margin * 100.0),
+            Column::Direction => opportunity.direction.to_string(),
+            Column::Vol1 => format!("{:.0}", opportunity.hub_one_volume),
+            Column::Vol2 => format!("{:.0}", opportunity.hub_two_volume),
+            Column::Epoch => opportunity
+                .snapshot_epoch
+                .map(|epoch| epoch.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// Enough digits to see the difference between two rates without printing
+// 300.123456789 for a divine
+pub fn format_rate(rate: f64) -> String {
+    let abs = rate.abs();
+    if abs >= 100.0 {
+        format!("{:.1}", rate)
+    } else if abs >= 1.0 {
+        format!("{:.3}", rate)
+    } else {
+        format!("{:.5}", rate)
+    }
+}
+
+pub fn write_table(
+    out: &mut impl Write,
+    opportunities: &[&Opportunity],
+    columns: &[Column],
+    color: bool,
+) -> io::Result<()> {
+    let rows: Vec<Vec<String>> = opportunities
+        .iter()
+        .map(|opportunity| columns.iter().map(|c| c.cell(opportunity)).collect())
+        .collect();
+
+    // Work out widths on the plain text, the color codes don't take up space
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(idx, column)| {
+            rows.iter()
+                .map(|row| row[idx].chars().count())
+                .chain([column.title().len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let titles: Vec<String> = columns.iter().map(|c| c.title().to_string()).collect();
+    writeln!(out, "{}", format_row(&titles, columns, &widths))?;
+    let rule_width = widths.iter().sum::<usize>() + 3 * widths.len().saturating_sub(1);
+    writeln!(out, "{}", "-".repeat(rule_width))?;
+
+    for (opportunity, row) in opportunities.iter().zip(&rows) {
+        let line = format_row(row, columns, &widths);
+        let paint = if !color || opportunity.margin == 0.0 {
+            None
+        } else {
+            match opportunity.direction {
+                Direction::Forward => Some(GREEN),
+                Direction::Reverse => Some(RED),
+            }
+        };
+        match paint {
+            Some(code) => writeln!(out, "{}{}{}", code, line, RESET)?,
+            None => writeln!(out, "{}", line)?,
+        }
+    }
+    Ok(())
+}
+
+fn format_row(cells: &[String], columns: &[Column], widths: &[usize]) -> String {
+    cells
+        .iter()
+        .zip(columns)
+        .zip(widths)
+        .map(|((cell, column), &width)| {
+            if column.right_aligned() {
+                format!("{:>width$}", cell)
+            } else {
+                format!("{:<width$}", cell)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// For routes that are in both snapshots of a diff, where the interesting bit
+// is how far the margin moved rather than the rates
+pub fn write_margin_changes(
+    out: &mut impl Write,
+    persisted: &[PersistedOpportunity],
+    color: bool,
+) -> io::Result<()> {
+    let routes: Vec<String> = persisted
+        .iter()
+        .map(|p| {
+            let o = &p.opportunity;
+            format!("{} -> {} -> {}", o.hub_one, o.bridge, o.hub_two)
+        })
+        .collect();
+    let width = routes.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+
+    for (p, route) in persisted.iter().zip(&routes) {
+        let line = format!(
+            "{:<width$}   {:>+7.2}% -> {:>+7.2}%   ({:+.2} pts)",
+            route,
+            p.previous_margin * 100.0,
+            p.opportunity.margin * 100.0,
+            p.margin_change * 100.0,
+        );
+        let paint = if !color || p.margin_change == 0.0 {
+            None
+        } else if p.margin_change > 0.0 {
+            Some(GREEN)
+        } else {
+            Some(RED)
+        };
+        match paint {
+            Some(code) => writeln!(out, "{}{}{}", code, line, RESET)?,
+            None => writeln!(out, "{}", line)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(300.123456), "300.1");
+        assert_eq!(format_rate(12.3456789), "12.346");
+        assert_eq!(format_rate(0.0123456789), "0.01235");
+    }
+
+    #[test]
+    fn test_columns_line_up() {
+        let first = Opportunity {
+            hub_one_volume: 12345.678,
+            ..Opportunity::sample("Vaal Orb", 0.1)
+        };
+        let second = Opportunity::sample("Gemcutter's Prism", -0.1);
+        let mut out = Vec::new();
+        write_table(&mut out, &[&first, &second], DEFAULT_COLUMNS, false).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Hub 1  | Bridge            | Hub 2 |"));
+        // Every row is the same width and the separators line up
+        let pipes: Vec<Vec<usize>> = [lines[0], lines[2], lines[3]]
+            .iter()
+            .map(|line| line.match_indices('|').map(|(idx, _)| idx).collect())
+            .collect();
+        assert_eq!(pipes[0], pipes[1]);
+        assert_eq!(pipes[0], pipes[2]);
+        assert!(lines[2].contains("+10.00%"));
+        assert!(lines[3].contains("-10.00%"));
+        assert!(lines[2].contains("12346"));
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_color_by_margin_sign() {
+        let up = Opportunity::sample("Vaal Orb", 0.1);
+        let down = Opportunity::sample("Vaal Orb", -0.1);
+        let mut out = Vec::new();
+        write_table(
+            &mut out,
+            &[&up, &down],
+            &[Column::Bridge, Column::Margin],
+            true,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("Bridge"));
+        assert!(lines[2].starts_with(GREEN) && lines[2].ends_with(RESET));
+        assert!(lines[3].starts_with(RED));
+    }
+}
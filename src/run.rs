@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::api;
+use crate::cache::{self, SnapshotCache, prune};
+use crate::config::Config;
+use crate::db;
+use crate::models::api_models::ExchangeRecord;
+use crate::models::logic_models::Opportunity;
+use crate::output::RunMetadata;
+use crate::source::{MarketSource, Poe2Scout, ReplaySource};
+use crate::synth::{self, SynthConfig};
+
+// Where the snapshot for a run comes from, picked by the command line flags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotChoice {
+    // Any snapshot json, plain or compressed
+    File(PathBuf),
+    // A made up market from this seed with a few mispricings planted in it
+    Demo(u64),
+    // A cached snapshot, the newest one if there's no epoch
+    Cached(Option<u64>),
+    // Responses saved by `record`
+    Replay(PathBuf),
+    // The newest snapshot from poe2scout
+    Live,
+}
+
+// Figures out which snapshot we're analyzing and gets it, from the api, the
+// cache or a file depending on the choice.
+pub fn get_snapshot(
+    choice: &SnapshotChoice,
+    config: &Config,
+) -> Result<(Vec<ExchangeRecord>, RunMetadata), Box<dyn Error>> {
+    let generated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let (records, snapshot_epoch, source) = match choice {
+        SnapshotChoice::File(path) => {
+            eprintln!("Analyzing snapshot file {}", path.display());
+            let records = cache::read_snapshot_file(path)?;
+            // Worth a guess from the name, it's probably one of ours
+            let epoch = path
+                .file_name()
+                .and_then(|name| cache::parse_snapshot_file_name(&name.to_string_lossy()));
+            (records, epoch, format!("file:{}", path.display()))
+        }
+        SnapshotChoice::Demo(seed) => {
+            let synth_config = SynthConfig {
+                planted_loops: 3,
+                ..Default::default()
+            };
+            let market = synth::generate(*seed, 1, &synth_config);
+            eprintln!("Demo market from seed {}, planted:", seed);
+            for plant in &market.planted {
+                eprintln!(
+                    "  {} at {:.2}x its value against {}",
+                    plant.bridge, plant.factor, plant.hub
+                );
+            }
+            // No epoch, so none of this ends up in the database
+            (market.records, None, format!("synth:{}", seed))
+        }
+        SnapshotChoice::Cached(epoch) => {
+            let cache = open_cache(config)?;
+            let (epoch, records) = cache::load_cached(&cache, *epoch)?;
+            (records, Some(epoch), "cache".to_string())
+        }
+        SnapshotChoice::Replay(dir) => {
+            eprintln!("Replaying recorded responses from {}", dir.display());
+            let source = ReplaySource::open(dir)?;
+            let (epoch, records) = fetch_newest(&source, &mut open_cache(config)?, config)?;
+            (records, Some(epoch), source.name().to_string())
+        }
+        SnapshotChoice::Live => {
+            let source = Poe2Scout::new(api::build_client()?);
+            let (epoch, records) = fetch_newest(&source, &mut open_cache(config)?, config)?;
+            (records, Some(epoch), source.name().to_string())
+        }
+    };
+
+    let metadata = RunMetadata {
+        snapshot_epoch,
+        source,
+        generated_at,
+    };
+    Ok((records, metadata))
+}
+
+// The snapshot cache set up the way the config wants it
+pub fn open_cache(config: &Config) -> Result<SnapshotCache, Box<dyn Error>> {
+    let mut cache = SnapshotCache::open(&config.data_dir)?;
+    cache.set_compression(config.cache.compression);
+    if let Some(newest_cached) = cache.newest() {
+        eprintln!(
+            "Cache holds {} snapshots, newest is {}",
+            cache.entries().len(),
+            newest_cached.epoch
+        );
+    }
+    Ok(cache)
+}
+
+fn fetch_newest<S: MarketSource + ?Sized>(
+    source: &S,
+    cache: &mut SnapshotCache,
+    config: &Config,
+) -> Result<(u64, Vec<ExchangeRecord>), Box<dyn Error>> {
+    let epoch = source.latest_snapshot()?;
+    eprintln!("Most recent snapshot number: {}", epoch);
+    Ok((epoch, fetch_snapshot(epoch, source, cache, config)?))
+}
+
+// Everything that happens when we go get a snapshot from a live source: use
+// the cached copy if there's a good one, put it in the database, and tidy the
+// cache up afterwards if the config says to.
+pub fn fetch_snapshot<S: MarketSource + ?Sized>(
+    epoch: u64,
+    source: &S,
+    cache: &mut SnapshotCache,
+    config: &Config,
+) -> Result<Vec<ExchangeRecord>, Box<dyn Error>> {
+    let records = cache::get_freshest_data(epoch, cache, source)?;
+    if config.record_snapshots
+        && let Err(e) = cache::import_snapshot(cache, &config.database_path, epoch, &records)
+    {
+        eprintln!("Couldn't save snapshot to the database: {}", e);
+    }
+
+    if config.cache.auto_prune {
+        let actions = cache.prune(&config.cache.retention, false)?;
+        if !actions.is_empty() {
+            // stdout is for results, this is just housekeeping
+            prune::write_prune_actions(&mut io::stderr(), &actions, false)?;
+        }
+    }
+    Ok(records)
+}
+
+// Saves what a run found to the database if the config wants it. A failed
+// save only gets a warning, the results still go out.
+pub fn save_opportunities(config: &Config, epoch: Option<u64>, opportunities: &[Opportunity]) {
+    if !config.record_opportunities {
+        return;
+    }
+    let Some(epoch) = epoch else {
+        // Nothing to key the rows on
+        eprintln!("Not saving opportunities, the snapshot has no epoch");
+        return;
+    };
+    if let Err(e) = db::record_opportunities(&config.database_path, epoch, opportunities) {
+        eprintln!("Couldn't save opportunities to the database: {}", e);
+    }
+}
@@ -0,0 +1,344 @@
+//! Where snapshots come from. Everything downstream of fetching (the cache,
+//! watch, backtest, stats) only needs "what's the newest snapshot and what's
+//! in it", so a new exchange or aggregator just needs a [`MarketSource`].
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use reqwest::blocking::Client;
+use rusqlite::Connection;
+
+use crate::api;
+use crate::cache::SnapshotCache;
+use crate::db;
+use crate::models::api_models::{ExchangeRecord, ExchangeSnapshot};
+
+pub type SourceResult<T> = Result<T, Box<dyn Error>>;
+
+/// Something that can hand out snapshots of currency pairs
+pub trait MarketSource {
+    /// Short name for the run header and log messages
+    fn name(&self) -> &'static str;
+
+    /// The newest snapshot's id, the epoch for poe2scout
+    fn latest_snapshot(&self) -> SourceResult<u64>;
+
+    /// Every pair in a snapshot
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>>;
+
+    /// Every snapshot this source can give us, oldest first. Live sources
+    /// only have the newest one.
+    fn snapshot_ids(&self) -> SourceResult<Vec<u64>> {
+        Ok(vec![self.latest_snapshot()?])
+    }
+}
+
+/// Every snapshot in `source`, oldest first, fetched one at a time as we go
+/// since there can be a lot of them. Ones that won't load get skipped with a
+/// warning.
+pub fn history<S: MarketSource + ?Sized>(
+    source: &S,
+) -> SourceResult<impl Iterator<Item = (u64, Vec<ExchangeRecord>)> + '_> {
+    let ids = source.snapshot_ids()?;
+    Ok(ids
+        .into_iter()
+        .filter_map(move |id| match source.pairs(id) {
+            Ok(records) => Some((id, records)),
+            Err(e) => {
+                eprintln!("Skipping snapshot {}: {}", id, e);
+                None
+            }
+        }))
+}
+
+/// The poe2scout HTTP API
+pub struct Poe2Scout {
+    client: Client,
+}
+
+impl Poe2Scout {
+    pub fn new(client: Client) -> Self {
+        Poe2Scout { client }
+    }
+}
+
+impl MarketSource for Poe2Scout {
+    fn name(&self) -> &'static str {
+        "api"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        Ok(api::get_exchange_snapshot(&self.client)?.epoch)
+    }
+
+    // poe2scout only serves the newest pairs, so asking for anything but the
+    // id latest_snapshot just gave you is an error
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        let records = check_one_snapshot(api::get_newest_snapshot_pairs(&self.client)?)?;
+        // If the snapshot rolled over since the caller asked for the epoch
+        // these are the next one's pairs, and they'd end up cached under the
+        // wrong epoch. Better to fail and let the caller ask again.
+        check_epoch(snapshot_id, self.latest_snapshot()?)?;
+        Ok(records)
+    }
+}
+
+// The pairs don't carry the epoch, their snapshot id is poe2scout's own row
+// id, so all we can check is that they agree with each other
+fn check_one_snapshot(records: Vec<ExchangeRecord>) -> SourceResult<Vec<ExchangeRecord>> {
+    let mismatch = records
+        .iter()
+        .find(|r| r.snapshot_id != records[0].snapshot_id);
+    match mismatch {
+        Some(record) => Err(format!(
+            "got pairs from more than one snapshot ({} and {})",
+            records[0].snapshot_id, record.snapshot_id
+        )
+        .into()),
+        None => Ok(records),
+    }
+}
+
+fn check_epoch(asked_for: u64, newest: u64) -> SourceResult<()> {
+    if asked_for != newest {
+        return Err(format!(
+            "asked for snapshot {} but the newest one is {}",
+            asked_for, newest
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// What [`record`] names the `currencyExchangeSnapshot` response
+pub const RECORDED_SNAPSHOT_FILE: &str = "snapshot.json";
+/// ...and the `SnapshotPairs` one
+pub const RECORDED_PAIRS_FILE: &str = "pairs.json";
+
+/// Saves poe2scout's current responses into `dir` byte for byte, so
+/// [`ReplaySource`] can play them back later without the network. Gives back
+/// the recorded epoch.
+pub fn record(client: &Client, dir: &Path) -> SourceResult<u64> {
+    // Snapshot first, same order a live run asks in, so the pairs are at
+    // least as new as the epoch we say they're from
+    let snapshot = api::get_exchange_snapshot_raw(client)?;
+    let epoch = serde_json::from_str::<ExchangeSnapshot>(&snapshot)?.epoch;
+    let pairs = api::get_newest_snapshot_pairs_raw(client)?;
+    // Make sure they'll replay before we call it a fixture
+    check_one_snapshot(serde_json::from_str(&pairs)?)?;
+    check_epoch(epoch, api::get_exchange_snapshot(client)?.epoch)?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(RECORDED_SNAPSHOT_FILE), snapshot)?;
+    fs::write(dir.join(RECORDED_PAIRS_FILE), pairs)?;
+    Ok(epoch)
+}
+
+/// Plays back responses saved by [`record`] as if poe2scout had sent them
+pub struct ReplaySource {
+    epoch: u64,
+    pairs_path: PathBuf,
+}
+
+impl ReplaySource {
+    /// Reads the recorded snapshot up front so a bad fixture fails here
+    pub fn open(dir: &Path) -> SourceResult<Self> {
+        let snapshot = fs::read_to_string(dir.join(RECORDED_SNAPSHOT_FILE))?;
+        let snapshot: ExchangeSnapshot = serde_json::from_str(&snapshot)?;
+        Ok(ReplaySource {
+            epoch: snapshot.epoch,
+            pairs_path: dir.join(RECORDED_PAIRS_FILE),
+        })
+    }
+}
+
+impl MarketSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        Ok(self.epoch)
+    }
+
+    // Same as Poe2Scout, there's only the one set of pairs
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        check_epoch(snapshot_id, self.epoch)?;
+        let pairs = fs::read_to_string(&self.pairs_path)?;
+        check_one_snapshot(serde_json::from_str(&pairs)?)
+    }
+}
+
+impl MarketSource for SnapshotCache {
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        match self.newest() {
+            Some(entry) => Ok(entry.epoch),
+            None => Err("the cache is empty".into()),
+        }
+    }
+
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        Ok(self.load(snapshot_id)?)
+    }
+
+    fn snapshot_ids(&self) -> SourceResult<Vec<u64>> {
+        Ok(self.entries().iter().map(|entry| entry.epoch).collect())
+    }
+}
+
+/// The `exchange_rates` table in the SQLite history
+pub struct SqliteHistory {
+    conn: Connection,
+}
+
+impl SqliteHistory {
+    pub fn new(conn: Connection) -> Self {
+        SqliteHistory { conn }
+    }
+}
+
+impl MarketSource for SqliteHistory {
+    fn name(&self) -> &'static str {
+        "db"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        match db::get_snapshot_ids(&self.conn)?.last() {
+            Some(&id) => Ok(id),
+            None => Err("the database has no snapshots".into()),
+        }
+    }
+
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        let rows = db::get_snapshot_entries(&self.conn, snapshot_id)?;
+        Ok(rows.into_iter().map(ExchangeRecord::from).collect())
+    }
+
+    fn snapshot_ids(&self) -> SourceResult<Vec<u64>> {
+        Ok(db::get_snapshot_ids(&self.conn)?)
+    }
+}
+
+/// Snapshots held in memory, for tests and for feeding in data from
+/// somewhere we don't have a source for yet
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    snapshots: BTreeMap<u64, Vec<ExchangeRecord>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        MemorySource::default()
+    }
+
+    pub fn insert(&mut self, snapshot_id: u64, records: Vec<ExchangeRecord>) {
+        self.snapshots.insert(snapshot_id, records);
+    }
+}
+
+impl MarketSource for MemorySource {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        match self.snapshots.keys().next_back() {
+            Some(&id) => Ok(id),
+            None => Err("no snapshots loaded".into()),
+        }
+    }
+
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        match self.snapshots.get(&snapshot_id) {
+            Some(records) => Ok(records.clone()),
+            None => Err(format!("no snapshot {}", snapshot_id).into()),
+        }
+    }
+
+    fn snapshot_ids(&self) -> SourceResult<Vec<u64>> {
+        Ok(self.snapshots.keys().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(snapshot_id: u64, count: u64) -> Vec<ExchangeRecord> {
+        (0..count)
+            .map(|pair_id| ExchangeRecord {
+                pair_id,
+                snapshot_id,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_memory_source() {
+        let mut source = MemorySource::new();
+        assert!(source.latest_snapshot().is_err());
+        source.insert(9, records(9, 2));
+        source.insert(3, records(3, 1));
+        assert_eq!(source.latest_snapshot().unwrap(), 9);
+        assert_eq!(source.snapshot_ids().unwrap(), vec![3, 9]);
+        assert_eq!(source.pairs(9).unwrap().len(), 2);
+        assert!(source.pairs(4).is_err());
+    }
+
+    #[test]
+    fn test_replay_source() {
+        let dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay/synthetic_small");
+        let source = ReplaySource::open(&dir).unwrap();
+        assert_eq!(source.latest_snapshot().unwrap(), 77);
+        let pairs = source.pairs(77).unwrap();
+        assert!(!pairs.is_empty());
+        assert!(pairs.iter().all(|record| record.snapshot_id == 77));
+        assert!(source.pairs(78).is_err());
+        assert!(ReplaySource::open(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_replay_epoch_is_not_the_snapshot_id() {
+        let dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay/synthetic_epoch");
+        let source = ReplaySource::open(&dir).unwrap();
+        assert_eq!(source.latest_snapshot().unwrap(), 1760000000);
+        let pairs = source.pairs(1760000000).unwrap();
+        assert!(pairs.iter().all(|record| record.snapshot_id == 4321));
+        assert!(source.pairs(4321).is_err());
+    }
+
+    #[test]
+    fn test_check_one_snapshot() {
+        assert!(check_one_snapshot(Vec::new()).is_ok());
+        assert_eq!(check_one_snapshot(records(5, 3)).unwrap().len(), 3);
+        let mut mixed = records(5, 2);
+        mixed.extend(records(6, 1));
+        assert!(check_one_snapshot(mixed).is_err());
+    }
+
+    #[test]
+    fn test_sqlite_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::new_schema(&conn).unwrap();
+        let mut rows = records(8, 2);
+        rows.extend(records(7, 3));
+        db::insert_all_rows(&rows, &mut conn).unwrap();
+
+        let source = SqliteHistory::new(conn);
+        assert_eq!(source.latest_snapshot().unwrap(), 8);
+        let replayed: Vec<(u64, usize)> = history(&source)
+            .unwrap()
+            .map(|(id, records)| (id, records.len()))
+            .collect();
+        assert_eq!(replayed, vec![(7, 3), (8, 2)]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::models::logic_models::{AnalysisParams, Opportunity, RouteKey};
+use crate::source::{self, MarketSource, SourceResult};
+use crate::{diff, logic};
+
+// How a bridge's mispricings have behaved over the history, across every hub
+// pair it shows up between. A run is one stretch of consecutive snapshots a
+// route stayed an opportunity for.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BridgeStats {
+    pub bridge: String,
+    pub runs: usize,
+    // Runs still going at the last snapshot, so we don't know how long they'll
+    // last yet
+    pub ongoing: usize,
+    pub mean_run_length: f64,
+    pub longest_run: usize,
+    // From the first snapshot a route showed up in to the first one it was
+    // gone from, over the runs that ended. poe2scout epochs are unix times.
+    pub median_revert_secs: Option<u64>,
+    pub median_revert_snapshots: Option<f64>,
+    // Snapshot to snapshot, did the margin get further from zero or closer
+    pub grew: usize,
+    pub shrank: usize,
+}
+
+#[derive(Default)]
+struct BridgeTally {
+    run_lengths: Vec<usize>,
+    ongoing: usize,
+    revert_secs: Vec<u64>,
+    revert_snapshots: Vec<u64>,
+    grew: usize,
+    shrank: usize,
+}
+
+// Where an open run started: its epoch and how many snapshots in we were
+struct OpenRun {
+    start_epoch: u64,
+    start_index: usize,
+}
+
+// Expects the snapshots oldest first. Sorted with the longest lasting bridges
+// first since those are the ones worth acting on.
+pub fn persistence_stats(
+    snapshots: impl IntoIterator<Item = (u64, Vec<Opportunity>)>,
+) -> Vec<BridgeStats> {
+    let mut tallies: HashMap<String, BridgeTally> = HashMap::new();
+    let mut open_runs: HashMap<RouteKey, OpenRun> = HashMap::new();
+    let mut previous: Vec<Opportunity> = Vec::new();
+    let mut index = 0;
+
+    for (epoch, current) in snapshots {
+        let diff = diff::diff_opportunities(&previous, &current);
+        for opportunity in &diff.appeared {
+            open_runs.insert(
+                opportunity.route(),
+                OpenRun {
+                    start_epoch: epoch,
+                    start_index: index,
+                },
+            );
+        }
+        for opportunity in &diff.disappeared {
+            let Some(run) = open_runs.remove(&opportunity.route()) else {
+                continue;
+            };
+            let tally = tallies.entry(opportunity.bridge.clone()).or_default();
+            tally.run_lengths.push(index - run.start_index);
+            tally
+                .revert_secs
+                .push(epoch.saturating_sub(run.start_epoch));
+            tally
+                .revert_snapshots
+                .push((index - run.start_index) as u64);
+        }
+        for persisted in &diff.persisted {
+            let tally = tallies
+                .entry(persisted.opportunity.bridge.clone())
+                .or_default();
+            let (new, old) = (
+                persisted.opportunity.margin.abs(),
+                persisted.previous_margin.abs(),
+            );
+            if new > old {
+                tally.grew += 1;
+            } else if new < old {
+                tally.shrank += 1;
+            }
+        }
+        previous = current;
+        index += 1;
+    }
+
+    // Whatever's left never ended, it counts towards how long runs last but
+    // we can't say when it reverted
+    for (route, run) in open_runs {
+        let tally = tallies.entry(route.bridge).or_default();
+        tally.run_lengths.push(index - run.start_index);
+        tally.ongoing += 1;
+    }
+
+    let mut stats: Vec<BridgeStats> = tallies
+        .into_iter()
+        .map(|(bridge, mut tally)| BridgeStats {
+            bridge,
+            runs: tally.run_lengths.len(),
+            ongoing: tally.ongoing,
+            mean_run_length: tally.run_lengths.iter().sum::<usize>() as f64
+                / tally.run_lengths.len().max(1) as f64,
+            longest_run: tally.run_lengths.iter().copied().max().unwrap_or(0),
+            median_revert_secs: median(&mut tally.revert_secs).map(|m| m.round() as u64),
+            median_revert_snapshots: median(&mut tally.revert_snapshots),
+            grew: tally.grew,
+            shrank: tally.shrank,
+        })
+        .collect();
+    stats.sort_by(|a, b| {
+        b.mean_run_length
+            .total_cmp(&a.mean_run_length)
+            .then_with(|| a.bridge.cmp(&b.bridge))
+    });
+    stats
+}
+
+// Re-analyzes everything the source has and works out the stats from that
+pub fn source_stats<S: MarketSource + ?Sized>(
+    source: &S,
+    params: &AnalysisParams,
+) -> SourceResult<Vec<BridgeStats>> {
+    let snapshots = source::history(source)?.map(|(epoch, records)| {
+        (
+            epoch,
+            logic::analyze(records, Some(epoch), params).opportunities,
+        )
+    });
+    Ok(persistence_stats(snapshots))
+}
+
+fn median(values: &mut [u64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        Some(values[mid] as f64)
+    } else {
+        Some((values[mid - 1] + values[mid]) as f64 / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_and_reverts() {
+        let hour = 3600;
+        let snapshots = vec![
+            (0, vec![Opportunity::sample("Vaal Orb", 0.10)]),
+            (hour, vec![Opportunity::sample("Vaal Orb", 0.12)]),
+            (2 * hour, vec![Opportunity::sample("Vaal Orb", 0.08)]),
+            // Vaal reverts after 3 snapshots
+            (3 * hour, vec![Opportunity::sample("Regal Orb", -0.2)]),
+            // Regal after 1
+            (4 * hour, vec![Opportunity::sample("Vaal Orb", 0.1)]),
+            (5 * hour, vec![]),
+            (6 * hour, vec![Opportunity::sample("Regal Orb", 0.3)]),
+        ];
+        let stats = persistence_stats(snapshots);
+        assert_eq!(stats.len(), 2);
+
+        let vaal = &stats[0];
+        assert_eq!(vaal.bridge, "Vaal Orb");
+        assert_eq!((vaal.runs, vaal.ongoing, vaal.longest_run), (2, 0, 3));
+        assert_eq!(vaal.mean_run_length, 2.0);
+        // 3 hours and 1 hour
+        assert_eq!(vaal.median_revert_secs, Some(2 * hour));
+        assert_eq!(vaal.median_revert_snapshots, Some(2.0));
+        assert_eq!((vaal.grew, vaal.shrank), (1, 1));
+
+        let regal = &stats[1];
+        assert_eq!((regal.runs, regal.ongoing, regal.longest_run), (2, 1, 1));
+        assert_eq!(regal.median_revert_secs, Some(hour));
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [5, 1, 3]), Some(3.0));
+        assert_eq!(median(&mut [4, 1, 3, 2]), Some(2.5));
+    }
+}
@@ -0,0 +1,402 @@
+//! Made up markets that look enough like poe2scout's to run the whole
+//! engine on. The same seed always gives the same market, so they work as
+//! fixtures for tests and benchmarks, and for trying things out without
+//! hitting poe2scout.
+
+use crate::logic::HUBS;
+use crate::models::api_models::{CurrencyData, CurrencyInfo, ExchangeRecord};
+use crate::models::logic_models::TradingCurrencyType;
+use crate::source::{MarketSource, SourceResult};
+
+// Real names for the first few bridges so the output reads like the real
+// thing, anything past these gets a numbered one
+const BRIDGE_NAMES: [&str; 18] = [
+    "Orb of Alchemy",
+    "Regal Orb",
+    "Vaal Orb",
+    "Orb of Annulment",
+    "Gemcutter's Prism",
+    "Glassblower's Bauble",
+    "Artificer's Orb",
+    "Orb of Transmutation",
+    "Orb of Augmentation",
+    "Orb of Chance",
+    "Armourer's Scrap",
+    "Blacksmith's Whetstone",
+    "Arcanist's Etcher",
+    "Fracturing Orb",
+    "Lesser Jeweller's Orb",
+    "Greater Jeweller's Orb",
+    "Perfect Jeweller's Orb",
+    "Mirror of Kalandra",
+];
+
+/// What kind of market to make up
+#[derive(Debug, Clone)]
+pub struct SynthConfig {
+    /// Bridge currencies, on top of the three hubs
+    pub currencies: usize,
+    /// Roughly what a divine goes for in exalts, each market lands within 20%
+    /// of it
+    pub div_to_exalt: f64,
+    /// ...and a chaos, the same way
+    pub chaos_to_exalt: f64,
+    /// Volume on the divine and chaos pairs with exalts
+    pub hub_volume: f64,
+    /// Bridge pairs trade somewhere between this fraction of `hub_volume` and
+    /// all of it, so the thin end falls under the default min_vol
+    pub min_liquidity: f64,
+    /// How far each listed price can wander from the currency's real value,
+    /// as a fraction. Keep it well under min_profit_frac or the noise itself
+    /// turns up as opportunities.
+    pub noise: f64,
+    /// Chance each hub/bridge pair is listed at all
+    pub coverage: f64,
+    /// Bridges to misprice on purpose, see [`PlantedLoop`]
+    pub planted_loops: usize,
+    /// How far off the planted ones are, as a fraction
+    pub planted_margin: f64,
+}
+
+impl Default for SynthConfig {
+    fn default() -> Self {
+        SynthConfig {
+            currencies: 30,
+            div_to_exalt: 300.0,
+            chaos_to_exalt: 12.0,
+            hub_volume: 2_000_000.0,
+            min_liquidity: 0.001,
+            noise: 0.01,
+            coverage: 0.8,
+            planted_loops: 0,
+            planted_margin: 0.15,
+        }
+    }
+}
+
+/// A bridge listed at `factor` times its real value in its pair with `hub`.
+/// Its other pairs are right, so every route through it that starts at `hub`
+/// is off by `1 / factor - 1` and every one that ends there by `factor - 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlantedLoop {
+    pub bridge: String,
+    pub hub: TradingCurrencyType,
+    pub factor: f64,
+}
+
+/// A generated snapshot and the mispricings planted in it
+#[derive(Debug, Clone)]
+pub struct SynthMarket {
+    pub records: Vec<ExchangeRecord>,
+    pub div_to_exalt: f64,
+    pub chaos_to_exalt: f64,
+    pub planted: Vec<PlantedLoop>,
+}
+
+impl SynthMarket {
+    /// What one of `hub` is worth in exalts in this market, None if it isn't
+    /// a hub
+    pub fn hub_price(&self, hub: TradingCurrencyType) -> Option<f64> {
+        match hub {
+            TradingCurrencyType::Exalt => Some(1.0),
+            TradingCurrencyType::Chaos => Some(self.chaos_to_exalt),
+            TradingCurrencyType::Divine => Some(self.div_to_exalt),
+            TradingCurrencyType::Other => None,
+        }
+    }
+}
+
+/// Makes up a market from `seed`. Planted bridges are listed against all
+/// three hubs with no noise and plenty of volume, so they're always found and
+/// by exactly the planted amount.
+pub fn generate(seed: u64, snapshot_id: u64, config: &SynthConfig) -> SynthMarket {
+    let mut rng = SplitMix64(seed);
+    let div_to_exalt = config.div_to_exalt * rng.range(0.8, 1.2);
+    let chaos_to_exalt = config.chaos_to_exalt * rng.range(0.8, 1.2);
+    let mut market = SynthMarket {
+        records: Vec::new(),
+        div_to_exalt,
+        chaos_to_exalt,
+        planted: Vec::new(),
+    };
+
+    let mut records = vec![
+        synth_record(
+            1,
+            snapshot_id,
+            config.hub_volume,
+            (hub_name(TradingCurrencyType::Divine), div_to_exalt),
+            (hub_name(TradingCurrencyType::Exalt), 1.0),
+        ),
+        synth_record(
+            2,
+            snapshot_id,
+            config.hub_volume,
+            (hub_name(TradingCurrencyType::Chaos), chaos_to_exalt),
+            (hub_name(TradingCurrencyType::Exalt), 1.0),
+        ),
+    ];
+
+    // Which bridges get planted, and through which hub
+    let mut order: Vec<usize> = (0..config.currencies).collect();
+    rng.shuffle(&mut order);
+    let planted: Vec<(usize, PlantedLoop)> = order
+        .into_iter()
+        .take(config.planted_loops)
+        .map(|idx| {
+            let hub = HUBS[rng.below(HUBS.len())];
+            let factor = if rng.next_f64() < 0.5 {
+                1.0 + config.planted_margin
+            } else {
+                1.0 - config.planted_margin
+            };
+            let bridge = bridge_name(idx);
+            (
+                idx,
+                PlantedLoop {
+                    bridge,
+                    hub,
+                    factor,
+                },
+            )
+        })
+        .collect();
+
+    let mut pair_id = 10;
+    for idx in 0..config.currencies {
+        let name = bridge_name(idx);
+        // Cheap stuff is most of the market, the odd one is worth divines
+        let value = (rng.range(0.01f64.ln(), 500.0f64.ln())).exp();
+        let plant = planted.iter().find(|(i, _)| *i == idx).map(|(_, p)| p);
+
+        for hub in HUBS {
+            pair_id += 1;
+            let listed = rng.next_f64() < config.coverage;
+            let noise = 1.0 + config.noise * rng.range(-1.0, 1.0);
+            let liquidity = rng.range(config.min_liquidity.ln(), 0.0).exp();
+            let hub_first = rng.next_f64() < 0.5;
+
+            let (price, volume) = match plant {
+                Some(plant) if plant.hub == hub => (value * plant.factor, config.hub_volume),
+                Some(_) => (value, config.hub_volume),
+                None if listed => (value * noise, config.hub_volume * liquidity),
+                None => continue,
+            };
+            let hub_side = (
+                hub_name(hub),
+                market.hub_price(hub).expect("HUBS are all hubs"),
+            );
+            let bridge_side = (name.as_str(), price);
+            records.push(if hub_first {
+                synth_record(pair_id, snapshot_id, volume, hub_side, bridge_side)
+            } else {
+                synth_record(pair_id, snapshot_id, volume, bridge_side, hub_side)
+            });
+        }
+    }
+
+    market.records = records;
+    market.planted = planted.into_iter().map(|(_, plant)| plant).collect();
+    market
+}
+
+fn hub_name(hub: TradingCurrencyType) -> &'static str {
+    match hub {
+        TradingCurrencyType::Exalt => "Exalted Orb",
+        TradingCurrencyType::Chaos => "Chaos Orb",
+        TradingCurrencyType::Divine => "Divine Orb",
+        TradingCurrencyType::Other => unreachable!(),
+    }
+}
+
+fn bridge_name(idx: usize) -> String {
+    match BRIDGE_NAMES.get(idx) {
+        Some(name) => name.to_string(),
+        None => format!("Synthetic Orb {}", idx + 1),
+    }
+}
+
+fn currency_info(text: &str) -> CurrencyInfo {
+    CurrencyInfo {
+        api_id: text.to_lowercase().replace([' ', '\''], "-"),
+        text: text.to_string(),
+        category_api_id: "currency".to_string(),
+        ..Default::default()
+    }
+}
+
+fn synth_record(
+    pair_id: u64,
+    snapshot_id: u64,
+    volume: f64,
+    one: (&str, f64),
+    two: (&str, f64),
+) -> ExchangeRecord {
+    // volume is in exalts, like poe2scout's
+    let data = |price: f64| CurrencyData {
+        relative_price: price,
+        value_traded: volume / 2.0,
+        volume_traded: (volume / 2.0 / price) as u64,
+        ..Default::default()
+    };
+    ExchangeRecord {
+        pair_id,
+        snapshot_id,
+        volume,
+        currency_one: currency_info(one.0),
+        currency_two: currency_info(two.0),
+        currency_one_data: data(one.1),
+        currency_two_data: data(two.1),
+    }
+}
+
+// Our own tiny generator instead of the rand crate so a seed keeps giving the
+// same market no matter what a dependency bump does to its streams
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// A history of made up snapshots, ids 1 to `snapshots`. Each one is its own
+/// market from the seed and its id, they don't drift from one to the next
+/// like a real history would.
+pub struct SynthSource {
+    seed: u64,
+    snapshots: u64,
+    config: SynthConfig,
+}
+
+impl SynthSource {
+    pub fn new(seed: u64, snapshots: u64, config: SynthConfig) -> Self {
+        SynthSource {
+            seed,
+            snapshots,
+            config,
+        }
+    }
+
+    /// The market behind `snapshot_id`, planted loops and all
+    pub fn market(&self, snapshot_id: u64) -> SynthMarket {
+        let seed = self
+            .seed
+            .wrapping_add(snapshot_id.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        generate(seed, snapshot_id, &self.config)
+    }
+}
+
+impl MarketSource for SynthSource {
+    fn name(&self) -> &'static str {
+        "synth"
+    }
+
+    fn latest_snapshot(&self) -> SourceResult<u64> {
+        match self.snapshots {
+            0 => Err("no snapshots to make up".into()),
+            n => Ok(n),
+        }
+    }
+
+    fn pairs(&self, snapshot_id: u64) -> SourceResult<Vec<ExchangeRecord>> {
+        if snapshot_id == 0 || snapshot_id > self.snapshots {
+            return Err(format!("no snapshot {}", snapshot_id).into());
+        }
+        Ok(self.market(snapshot_id).records)
+    }
+
+    fn snapshot_ids(&self) -> SourceResult<Vec<u64>> {
+        Ok((1..=self.snapshots).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic;
+    use crate::models::logic_models::AnalysisParams;
+    use crate::validation;
+
+    fn to_json(market: &SynthMarket) -> String {
+        serde_json::to_string(&market.records).unwrap()
+    }
+
+    #[test]
+    fn test_same_seed_same_market() {
+        let config = SynthConfig {
+            planted_loops: 3,
+            ..Default::default()
+        };
+        let one = generate(42, 1, &config);
+        let two = generate(42, 1, &config);
+        assert_eq!(to_json(&one), to_json(&two));
+        assert_eq!(one.planted, two.planted);
+        assert_eq!(one.planted.len(), 3);
+        assert_ne!(to_json(&one), to_json(&generate(43, 1, &config)));
+    }
+
+    #[test]
+    fn test_market_is_valid() {
+        let market = generate(7, 1, &SynthConfig::default());
+        let count = market.records.len();
+        let (_, report) = validation::validate_records(market.records);
+        assert!(report.dropped.is_empty());
+        assert_eq!(report.kept, count);
+        // Some under the default min_vol, some over
+        let market = generate(7, 1, &SynthConfig::default());
+        assert!(market.records.iter().any(|r| r.volume < 10000.0));
+        assert!(market.records.iter().any(|r| r.volume >= 10000.0));
+    }
+
+    #[test]
+    fn test_noise_alone_finds_nothing() {
+        for seed in 0..20 {
+            let market = generate(seed, 1, &SynthConfig::default());
+            let analysis = logic::analyze(market.records, None, &AnalysisParams::default());
+            assert!(
+                analysis.opportunities.is_empty(),
+                "seed {} found {:?}",
+                seed,
+                analysis.opportunities
+            );
+        }
+    }
+
+    #[test]
+    fn test_synth_source() {
+        let source = SynthSource::new(5, 3, SynthConfig::default());
+        assert_eq!(source.latest_snapshot().unwrap(), 3);
+        assert_eq!(source.snapshot_ids().unwrap(), vec![1, 2, 3]);
+        let pairs = source.pairs(2).unwrap();
+        assert!(pairs.iter().all(|r| r.snapshot_id == 2));
+        assert_ne!(
+            serde_json::to_string(&pairs).unwrap(),
+            serde_json::to_string(&source.pairs(3).unwrap()).unwrap()
+        );
+        assert!(source.pairs(4).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use crate::models::api_models::ExchangeRecord;
+use crate::models::logic_models::{DropReason, DroppedRecord, ValidationReport};
+
+/// poe2scout sometimes hands us garbage: zero prices, the same pair twice or a
+/// currency traded against itself. Any of those blow up the ratio math later
+/// (divide by zero -> inf -> NaN in the sort), so throw them out up front and
+/// keep track of what we threw out.
+pub fn validate_records(records: Vec<ExchangeRecord>) -> (Vec<ExchangeRecord>, ValidationReport) {
+    let mut seen_pair_ids = HashSet::new();
+    let mut report = ValidationReport::default();
+    let mut kept = Vec::with_capacity(records.len());
+
+    for record in records {
+        match check_record(&record, &mut seen_pair_ids) {
+            Some(reason) => report.dropped.push(DroppedRecord {
+                pair_id: record.pair_id,
+                currency_one: record.currency_one.text,
+                currency_two: record.currency_two.text,
+                reason,
+            }),
+            None => kept.push(record),
+        }
+    }
+    report.kept = kept.len();
+    (kept, report)
+}
+
+fn check_record(record: &ExchangeRecord, seen_pair_ids: &mut HashSet<u64>) -> Option<DropReason> {
+    for price in [
+        record.currency_one_data.relative_price,
+        record.currency_two_data.relative_price,
+    ] {
+        if !is_sane_price(price) {
+            return Some(DropReason::BadPrice(price));
+        }
+    }
+    if record.currency_one.text == record.currency_two.text {
+        return Some(DropReason::SelfPair);
+    }
+    // Only remember the id once the record is otherwise good, so a broken
+    // first copy doesn't knock out a good second one.
+    if !seen_pair_ids.insert(record.pair_id) {
+        return Some(DropReason::DuplicatePairId);
+    }
+    None
+}
+
+fn is_sane_price(price: f64) -> bool {
+    price.is_finite() && price > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        pair_id: u64,
+        one: &str,
+        two: &str,
+        price_one: f64,
+        price_two: f64,
+    ) -> ExchangeRecord {
+        let mut exchange = ExchangeRecord {
+            pair_id,
+            ..Default::default()
+        };
+        exchange.currency_one.text = one.to_string();
+        exchange.currency_two.text = two.to_string();
+        exchange.currency_one_data.relative_price = price_one;
+        exchange.currency_two_data.relative_price = price_two;
+        exchange
+    }
+
+    #[test]
+    fn test_keeps_good_records() {
+        let records = vec![
+            record(1, "Exalted Orb", "Vaal Orb", 1.0, 2.5),
+            record(2, "Divine Orb", "Exalted Orb", 300.0, 1.0),
+        ];
+        let (kept, report) = validate_records(records);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(report.kept, 2);
+        assert!(report.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_drops_bad_prices() {
+        let records = vec![
+            record(1, "Exalted Orb", "Vaal Orb", 0.0, 2.5),
+            record(2, "Exalted Orb", "Orb of Annulment", 1.0, -3.0),
+            record(3, "Exalted Orb", "Regal Orb", f64::NAN, 1.0),
+            record(4, "Exalted Orb", "Orb of Alchemy", 1.0, f64::INFINITY),
+        ];
+        let (kept, report) = validate_records(records);
+        assert!(kept.is_empty());
+        assert_eq!(report.dropped.len(), 4);
+        assert!(
+            report
+                .dropped
+                .iter()
+                .all(|d| matches!(d.reason, DropReason::BadPrice(_)))
+        );
+    }
+
+    #[test]
+    fn test_drops_self_pair() {
+        let (kept, report) = validate_records(vec![record(1, "Vaal Orb", "Vaal Orb", 1.0, 1.0)]);
+        assert!(kept.is_empty());
+        assert_eq!(report.dropped[0].reason, DropReason::SelfPair);
+    }
+
+    #[test]
+    fn test_drops_duplicate_pair_id_keeps_first() {
+        let records = vec![
+            record(7, "Exalted Orb", "Vaal Orb", 1.0, 2.5),
+            record(7, "Exalted Orb", "Vaal Orb", 1.0, 9.0),
+        ];
+        let (kept, report) = validate_records(records);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].currency_two_data.relative_price, 2.5);
+        assert_eq!(report.dropped[0].reason, DropReason::DuplicatePairId);
+    }
+
+    #[test]
+    fn test_bad_duplicate_does_not_shadow_good_one() {
+        let records = vec![
+            record(7, "Exalted Orb", "Vaal Orb", 0.0, 2.5),
+            record(7, "Exalted Orb", "Vaal Orb", 1.0, 2.5),
+        ];
+        let (kept, report) = validate_records(records);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(report.dropped[0].reason, DropReason::BadPrice(0.0));
+    }
+}
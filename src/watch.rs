@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::alert::Alerter;
+use crate::cache::SnapshotCache;
+use crate::config::Config;
+use crate::diff::{self, OpportunityDiff, Streaks};
+use crate::logic::{self, Analysis};
+use crate::models::logic_models::{AnalysisParams, Opportunity};
+use crate::output::{self, OutputOptions};
+use crate::run;
+use crate::source::MarketSource;
+
+// How long to nap between checks of the shutdown flag, so Ctrl-C doesn't
+// have to wait out a whole poll interval
+const SLEEP_SLICE: Duration = Duration::from_millis(250);
+
+pub fn watch<S: MarketSource + ?Sized>(
+    source: &S,
+    cache: &mut SnapshotCache,
+    config: &Config,
+    params: &AnalysisParams,
+    options: &OutputOptions,
+    mut alerter: Option<Alerter>,
+    running: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let watch_config = &config.watch;
+    let mut last_epoch: Option<u64> = None;
+    let mut last_opportunities: Vec<Opportunity> = Vec::new();
+    let mut streaks = Streaks::default();
+    let mut failures: u32 = 0;
+
+    eprintln!(
+        "Watching for new snapshots every {}s, Ctrl-C to stop",
+        watch_config.interval_secs
+    );
+    while running.load(Ordering::SeqCst) {
+        let delay = match poll_once(source, cache, config, params, last_epoch) {
+            Ok(Some((epoch, analysis))) => {
+                failures = 0;
+                let diff = diff::diff_opportunities(&last_opportunities, &analysis.opportunities);
+                streaks.update(&diff);
+                let changed = new_or_changed(&diff, watch_config.change_threshold);
+                match report_changes(epoch, &diff, &changed, &streaks, options) {
+                    Ok(()) => {}
+                    // Whoever was reading went away (watch | head), same as
+                    // a Ctrl-C
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                    Err(e) => return Err(e),
+                }
+                run::save_opportunities(config, Some(epoch), &analysis.opportunities);
+                if let Some(alerter) = &mut alerter {
+                    // A dead webhook shouldn't stop us watching
+                    if let Err(e) = alerter.send(epoch, &analysis.opportunities, now_secs()) {
+                        eprintln!("Couldn't send alert: {}", e);
+                    }
+                }
+                last_opportunities = analysis.opportunities;
+                last_epoch = Some(epoch);
+                Duration::from_secs(watch_config.interval_secs)
+            }
+            Ok(None) => {
+                failures = 0;
+                Duration::from_secs(watch_config.interval_secs)
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = backoff_delay(
+                    failures,
+                    watch_config.retry_secs,
+                    watch_config.max_backoff_secs,
+                );
+                eprintln!(
+                    "Poll failed ({} in a row), trying again in {}s: {}",
+                    failures,
+                    delay.as_secs(),
+                    e
+                );
+                delay
+            }
+        };
+        sleep_while_running(delay, &running);
+    }
+    eprintln!("Stopped watching");
+    Ok(())
+}
+
+// Returns None when there's nothing new since last time
+fn poll_once<S: MarketSource + ?Sized>(
+    source: &S,
+    cache: &mut SnapshotCache,
+    config: &Config,
+    params: &AnalysisParams,
+    last_epoch: Option<u64>,
+) -> Result<Option<(u64, Analysis)>, Box<dyn std::error::Error>> {
+    let epoch = source.latest_snapshot()?;
+    if last_epoch == Some(epoch) {
+        return Ok(None);
+    }
+
+    let records = run::fetch_snapshot(epoch, source, cache, config)?;
+    Ok(Some((epoch, logic::analyze(records, Some(epoch), params))))
+}
+
+// Anything we didn't see last snapshot, or whose margin moved more than the
+// threshold since then
+fn new_or_changed(diff: &OpportunityDiff, threshold: f64) -> Vec<Opportunity> {
+    let moved = diff
+        .persisted
+        .iter()
+        .filter(|p| p.margin_change.abs() >= threshold)
+        .map(|p| p.opportunity.clone());
+    diff.appeared.iter().cloned().chain(moved).collect()
+}
+
+fn report_changes(
+    epoch: u64,
+    diff: &OpportunityDiff,
+    changed: &[Opportunity],
+    streaks: &Streaks,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let ranked =
+        logic::rank_opportunities(changed, options.rank, options.top.unwrap_or(usize::MAX));
+    output::write_update(&mut stdout, options, epoch, diff, &ranked, streaks)?;
+    stdout.flush()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn backoff_delay(failures: u32, retry_secs: u64, max_backoff_secs: u64) -> Duration {
+    let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+    Duration::from_secs(retry_secs.saturating_mul(factor).min(max_backoff_secs))
+}
+
+fn sleep_while_running(delay: Duration, running: &AtomicBool) {
+    let mut slept = Duration::ZERO;
+    while slept < delay && running.load(Ordering::SeqCst) {
+        let slice = SLEEP_SLICE.min(delay - slept);
+        thread::sleep(slice);
+        slept += slice;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_or_changed() {
+        let previous = vec![
+            Opportunity::sample("Vaal Orb", 0.10),
+            Opportunity::sample("Regal Orb", 0.08),
+        ];
+        let current = vec![
+            // barely moved
+            Opportunity::sample("Vaal Orb", 0.101),
+            // moved a lot
+            Opportunity::sample("Regal Orb", 0.12),
+            // new
+            Opportunity::sample("Orb of Annulment", -0.2),
+        ];
+        let diff = diff::diff_opportunities(&previous, &current);
+        let changed = new_or_changed(&diff, 0.005);
+        let bridges: Vec<&str> = changed.iter().map(|o| o.bridge.as_str()).collect();
+        assert_eq!(bridges, vec!["Orb of Annulment", "Regal Orb"]);
+    }
+
+    #[test]
+    fn test_everything_is_new_the_first_time() {
+        let current = vec![Opportunity::sample("Vaal Orb", 0.1)];
+        let diff = diff::diff_opportunities(&[], &current);
+        assert_eq!(new_or_changed(&diff, 0.005).len(), 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        assert_eq!(backoff_delay(1, 30, 1800), Duration::from_secs(30));
+        assert_eq!(backoff_delay(2, 30, 1800), Duration::from_secs(60));
+        assert_eq!(backoff_delay(4, 30, 1800), Duration::from_secs(240));
+        assert_eq!(backoff_delay(10, 30, 1800), Duration::from_secs(1800));
+        assert_eq!(backoff_delay(200, 30, 1800), Duration::from_secs(1800));
+    }
+
+    #[test]
+    fn test_sleep_stops_when_not_running() {
+        let running = AtomicBool::new(false);
+        let start = std::time::Instant::now();
+        sleep_while_running(Duration::from_secs(60), &running);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,113 @@
+// Runs the real binary over recorded poe2scout responses and checks what it
+// finds against a golden file. Fixtures named synthetic_* are hand written in
+// the same shape instead of recorded. Set UPDATE_GOLDEN=1 to rewrite the golden files
+// after a change that's meant to move the output.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::Value;
+
+fn fixture_dir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/replay")
+        .join(name)
+}
+
+// Somewhere to put the cache and db so nothing lands in the repo
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "poe_flip_finder_replay_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run_replay(fixture: &str, work_dir: &Path) -> Value {
+    let config = work_dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "data_dir = {:?}\ndatabase_path = {:?}\nrecord_opportunities = false\n",
+            work_dir.join("data"),
+            work_dir.join("flips.db"),
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_poe_flip_finder"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--replay")
+        .arg(fixture_dir(fixture))
+        .args(["--format", "json"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "replay failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut result: Value = serde_json::from_slice(&output.stdout).unwrap();
+    // The only thing that changes run to run
+    result["header"]
+        .as_object_mut()
+        .unwrap()
+        .remove("generated_at");
+    result
+}
+
+fn assert_golden(fixture: &str, actual: &Value) {
+    let path = fixture_dir(fixture).join("expected.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let mut golden = serde_json::to_string_pretty(actual).unwrap();
+        golden.push('\n');
+        fs::write(&path, golden).unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        actual,
+        &expected,
+        "output doesn't match {}, rerun with UPDATE_GOLDEN=1 if that's on purpose",
+        path.display()
+    );
+}
+
+// Every recording under tests/fixtures/replay gets checked, so adding one is
+// just `record` plus UPDATE_GOLDEN=1
+#[test]
+fn test_replay_matches_golden() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay");
+    let mut fixtures: Vec<String> = fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    for fixture in &fixtures {
+        let work_dir = temp_dir(&format!("golden_{}", fixture));
+        let result = run_replay(fixture, &work_dir);
+        assert_golden(fixture, &result);
+        let _ = fs::remove_dir_all(&work_dir);
+    }
+}
+
+#[test]
+fn test_replay_from_cache_matches_fresh() {
+    let work_dir = temp_dir("cached");
+    let fresh = run_replay("synthetic_small", &work_dir);
+    // Second time round the snapshot comes out of the cache instead
+    assert!(work_dir.join("data/response_77.json").exists());
+    let cached = run_replay("synthetic_small", &work_dir);
+    assert_eq!(fresh, cached);
+    let _ = fs::remove_dir_all(&work_dir);
+}